@@ -1,4 +1,9 @@
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/wireguard_control.proto")?;
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("wireguard_control_descriptor.bin"))
+        .compile(&["proto/wireguard_control.proto"], &["proto"])?;
     Ok(())
 }
@@ -0,0 +1,18 @@
+-- Plaintext keys are gone once encrypted, so reverting would lose them.
+-- Refuse instead of leaving profiles without a private key.
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM profiles WHERE private_key IS NULL) THEN
+        RAISE EXCEPTION 'Profiles have encrypted private keys only, decrypt them into private_key before reverting';
+    END IF;
+END
+$$;
+
+ALTER TABLE profiles ALTER COLUMN private_key SET NOT NULL;
+
+ALTER TABLE profiles
+    DROP COLUMN master_key_id,
+    DROP COLUMN wrapped_data_key,
+    DROP COLUMN data_key_nonce,
+    DROP COLUMN encrypted_private_key,
+    DROP COLUMN private_key_nonce;
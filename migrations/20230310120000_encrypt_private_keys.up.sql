@@ -0,0 +1,11 @@
+-- Existing private keys stay in plaintext until `telegram_bot encrypt-private-keys`
+-- is run, see `Storage::encrypt_plaintext_private_keys`
+
+ALTER TABLE profiles ALTER COLUMN private_key DROP NOT NULL;
+
+ALTER TABLE profiles
+    ADD COLUMN master_key_id TEXT,
+    ADD COLUMN wrapped_data_key BYTEA,
+    ADD COLUMN data_key_nonce BYTEA,
+    ADD COLUMN encrypted_private_key BYTEA,
+    ADD COLUMN private_key_nonce BYTEA;
@@ -0,0 +1,3 @@
+DROP TABLE server_keys;
+
+DROP TYPE server_key_status;
@@ -0,0 +1,18 @@
+-- Server keypairs, the first one is seeded from `private_key`/`public_key` in config
+
+CREATE TYPE server_key_status AS ENUM ('pending', 'active', 'retired');
+
+CREATE TABLE IF NOT EXISTS server_keys (
+    id SERIAL PRIMARY KEY,
+    public_key TEXT NOT NULL UNIQUE,
+    master_key_id TEXT NOT NULL,
+    wrapped_data_key BYTEA NOT NULL,
+    data_key_nonce BYTEA NOT NULL,
+    encrypted_private_key BYTEA NOT NULL,
+    private_key_nonce BYTEA NOT NULL,
+    status server_key_status NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    activate_at TIMESTAMPTZ,
+    activated_at TIMESTAMPTZ,
+    reported_at TIMESTAMPTZ
+);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS profiles_interface_idx;
+
+ALTER TABLE profiles DROP COLUMN interface;
@@ -0,0 +1,3 @@
+ALTER TABLE profiles ADD COLUMN interface TEXT NOT NULL DEFAULT 'wg0';
+
+CREATE INDEX profiles_interface_idx ON profiles (interface);
@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS server_keys_server_id_idx;
+ALTER TABLE server_keys DROP COLUMN server_id;
+
+ALTER TABLE profiles DROP CONSTRAINT IF EXISTS profiles_server_id_ip_key;
+ALTER TABLE profiles DROP COLUMN server_id;
+ALTER TABLE profiles ADD CONSTRAINT profiles_ip_key UNIQUE (ip);
+
+DROP TABLE servers;
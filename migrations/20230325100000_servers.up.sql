@@ -0,0 +1,20 @@
+-- Servers are seeded from `servers` in config, see `Storage::seed_servers`
+
+CREATE TABLE IF NOT EXISTS servers (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    region TEXT NOT NULL,
+    endpoint TEXT NOT NULL,
+    control_address TEXT NOT NULL,
+    interface TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Every server allocates addresses on its own, so they are unique per server only
+ALTER TABLE profiles ADD COLUMN server_id INT REFERENCES servers(id);
+ALTER TABLE profiles DROP CONSTRAINT IF EXISTS profiles_ip_key;
+ALTER TABLE profiles ADD CONSTRAINT profiles_server_id_ip_key UNIQUE (server_id, ip);
+
+ALTER TABLE server_keys ADD COLUMN server_id INT REFERENCES servers(id) ON DELETE CASCADE;
+CREATE INDEX server_keys_server_id_idx ON server_keys (server_id, status);
@@ -0,0 +1,9 @@
+DROP TABLE IF EXISTS traffic_hourly;
+DROP TABLE IF EXISTS peer_counters;
+
+CREATE TABLE IF NOT EXISTS statistics (
+    ip INET NOT NULL UNIQUE,
+    timestamp TIMESTAMP NOT NULL,
+    tx BIGINT,
+    rx BIGINT
+);
@@ -0,0 +1,21 @@
+-- Last counters seen for every peer. WireGuard counters restart when a peer or
+-- interface is re-created, so history is accumulated from the deltas.
+CREATE TABLE IF NOT EXISTS peer_counters (
+    public_key TEXT PRIMARY KEY,
+    rx BIGINT NOT NULL,
+    tx BIGINT NOT NULL,
+    latest_handshake TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Keyed by profile rather than by key, as keys change on rotation and moves
+CREATE TABLE IF NOT EXISTS traffic_hourly (
+    user_id BIGINT NOT NULL,
+    profile TEXT NOT NULL,
+    hour TIMESTAMPTZ NOT NULL,
+    rx BIGINT NOT NULL DEFAULT 0,
+    tx BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (user_id, profile, hour)
+);
+
+DROP TABLE IF EXISTS statistics;
@@ -0,0 +1,11 @@
+-- Profile of the Telegram user, updated from every incoming update
+ALTER TABLE users ADD COLUMN username TEXT;
+ALTER TABLE users ADD COLUMN first_name TEXT;
+ALTER TABLE users ADD COLUMN last_name TEXT;
+ALTER TABLE users ADD COLUMN language_code TEXT;
+ALTER TABLE users ADD COLUMN first_seen_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE users ADD COLUMN last_seen_at TIMESTAMPTZ;
+
+-- Users are upserted by id from now on
+DELETE FROM users a USING users b WHERE a.ctid < b.ctid AND a.user_id = b.user_id;
+ALTER TABLE users ADD CONSTRAINT users_user_id_key UNIQUE (user_id);
@@ -0,0 +1,3 @@
+ALTER TABLE users DROP COLUMN notify_sessions;
+
+DROP TABLE IF EXISTS sessions;
@@ -0,0 +1,18 @@
+-- Connections of profiles derived from handshakes, see `presence::update`
+CREATE TABLE IF NOT EXISTS sessions (
+    id SERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    profile TEXT NOT NULL,
+    server_id INT NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    started_at TIMESTAMPTZ NOT NULL,
+    -- Latest handshake seen, the end of the session once it is closed
+    last_handshake_at TIMESTAMPTZ NOT NULL,
+    ended_at TIMESTAMPTZ,
+    rx BIGINT NOT NULL DEFAULT 0,
+    tx BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE INDEX sessions_profile_idx ON sessions (user_id, profile, started_at);
+CREATE INDEX sessions_open_idx ON sessions (server_id) WHERE ended_at IS NULL;
+
+ALTER TABLE users ADD COLUMN notify_sessions BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS session_endpoints;
+
+ALTER TABLE sessions DROP COLUMN endpoint;
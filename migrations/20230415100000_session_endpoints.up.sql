@@ -0,0 +1,10 @@
+-- Latest endpoint of the session and every endpoint it was seen from
+ALTER TABLE sessions ADD COLUMN endpoint TEXT;
+
+CREATE TABLE IF NOT EXISTS session_endpoints (
+    session_id INT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    endpoint TEXT NOT NULL,
+    seen_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX session_endpoints_session_id_idx ON session_endpoints (session_id, seen_at);
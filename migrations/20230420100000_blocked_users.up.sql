@@ -0,0 +1,3 @@
+-- Set when a broadcast finds that the user blocked the bot, cleared on the
+-- next update from the user
+ALTER TABLE users ADD COLUMN blocked BOOLEAN NOT NULL DEFAULT FALSE;
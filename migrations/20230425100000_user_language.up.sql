@@ -0,0 +1,3 @@
+-- Language chosen with /language, the one of the Telegram client is used
+-- while it is not set
+ALTER TABLE users ADD COLUMN language TEXT;
@@ -0,0 +1,119 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+};
+
+use crate::control_client::ControlError;
+
+/// Metrics of the bot, served by `metrics::serve`.
+pub struct BotMetrics {
+    pub registry: Registry,
+    /// Labeled by server, public key, profile and user id. Peers without a
+    /// profile are labeled `(unknown)` with an empty user id
+    pub peer_rx_bytes: IntGaugeVec,
+    pub peer_tx_bytes: IntGaugeVec,
+    pub peer_handshake_age: IntGaugeVec,
+    /// Labeled by server
+    pub profiles: IntGaugeVec,
+    /// Labeled by `UserStatus`
+    pub users: IntGaugeVec,
+    /// Labeled by server and method, retries included
+    pub grpc_duration: HistogramVec,
+    /// Labeled by server, method and kind of `ControlError`
+    pub grpc_errors: IntCounterVec,
+    /// Labeled by server
+    pub sync_duration: HistogramVec,
+    pub handler_errors: IntCounter,
+    pub reconcile_runs: IntCounter,
+    /// Labeled by server
+    pub reconcile_failures: IntCounterVec,
+    /// Runs which found live peers differing from the database
+    pub reconcile_drifts: IntCounter,
+    /// Labeled by server and action: added, removed or modified
+    pub reconciled_peers: IntCounterVec,
+}
+
+pub type BotMetricsPtr = Arc<BotMetrics>;
+
+impl BotMetrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("wednesday_vpn".into()), None)?;
+        let peer_labels = &["server", "public_key", "profile", "user_id"];
+        let metrics = Self {
+            peer_rx_bytes: IntGaugeVec::new(
+                Opts::new("peer_rx_bytes", "Bytes received from the peer"),
+                peer_labels,
+            )?,
+            peer_tx_bytes: IntGaugeVec::new(
+                Opts::new("peer_tx_bytes", "Bytes sent to the peer"),
+                peer_labels,
+            )?,
+            peer_handshake_age: IntGaugeVec::new(
+                Opts::new("peer_handshake_age_seconds", "Seconds since the latest handshake of the peer"),
+                peer_labels,
+            )?,
+            profiles: IntGaugeVec::new(Opts::new("profiles", "Number of profiles"), &["server"])?,
+            users: IntGaugeVec::new(Opts::new("users", "Number of users"), &["status"])?,
+            grpc_duration: HistogramVec::new(
+                HistogramOpts::new("grpc_request_duration_seconds", "Duration of control service calls"),
+                &["server", "method"],
+            )?,
+            grpc_errors: IntCounterVec::new(
+                Opts::new("grpc_errors_total", "Failed control service calls"),
+                &["server", "method", "kind"],
+            )?,
+            sync_duration: HistogramVec::new(
+                HistogramOpts::new("sync_duration_seconds", "Duration of full config syncs"),
+                &["server"],
+            )?,
+            handler_errors: IntCounter::new("handler_errors_total", "Telegram update handlers which failed")?,
+            reconcile_runs: IntCounter::new("reconcile_runs_total", "Runs of the reconciler")?,
+            reconcile_failures: IntCounterVec::new(
+                Opts::new("reconcile_failures_total", "Servers the reconciler could not reconcile"),
+                &["server"],
+            )?,
+            reconcile_drifts: IntCounter::new("reconcile_drifts_total", "Reconciler runs which corrected live peers")?,
+            reconciled_peers: IntCounterVec::new(
+                Opts::new("reconciled_peers_total", "Live peers corrected by the reconciler"),
+                &["server", "action"],
+            )?,
+            registry,
+        };
+        metrics.registry.register(Box::new(metrics.peer_rx_bytes.clone()))?;
+        metrics.registry.register(Box::new(metrics.peer_tx_bytes.clone()))?;
+        metrics.registry.register(Box::new(metrics.peer_handshake_age.clone()))?;
+        metrics.registry.register(Box::new(metrics.profiles.clone()))?;
+        metrics.registry.register(Box::new(metrics.users.clone()))?;
+        metrics.registry.register(Box::new(metrics.grpc_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.grpc_errors.clone()))?;
+        metrics.registry.register(Box::new(metrics.sync_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.handler_errors.clone()))?;
+        metrics.registry.register(Box::new(metrics.reconcile_runs.clone()))?;
+        metrics.registry.register(Box::new(metrics.reconcile_failures.clone()))?;
+        metrics.registry.register(Box::new(metrics.reconcile_drifts.clone()))?;
+        metrics.registry.register(Box::new(metrics.reconciled_peers.clone()))?;
+        Ok(metrics)
+    }
+
+    pub fn observe_call<T>(
+        &self,
+        server: &str,
+        method: &str,
+        elapsed: Duration,
+        result: &Result<T, ControlError>,
+    ) {
+        self.grpc_duration
+            .with_label_values(&[server, method])
+            .observe(elapsed.as_secs_f64());
+        if let Err(e) = result {
+            let kind = match e {
+                ControlError::Unreachable(_) => "unreachable",
+                ControlError::Rejected(_) => "rejected",
+                ControlError::InvalidRequest(_) => "invalid_request",
+            };
+            self.grpc_errors.with_label_values(&[server, method, kind]).inc();
+        }
+    }
+}
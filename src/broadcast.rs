@@ -0,0 +1,220 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use teloxide::{prelude::*, types::MessageId, ApiError, RequestError};
+use tokio::sync::mpsc;
+
+use crate::{
+    fleet::Fleet,
+    i18n::{tr, trf, Lang},
+    storage::StoragePtr,
+};
+
+/// Telegram allows about 30 messages a second to different chats.
+const SEND_INTERVAL: Duration = Duration::from_millis(50);
+/// Attempts of a message failing with a network error. Flood control
+/// waits do not count.
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+/// Users with a handshake within this many days have active profiles.
+const ACTIVE_DAYS: i64 = 30;
+/// Failed users listed in the report, so that it fits in a message.
+const MAX_REPORTED_FAILURES: usize = 10;
+
+/// Who a broadcast goes to. Only granted users who did not block the bot
+/// are ever targeted.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BroadcastTarget {
+    #[serde(rename = "a")]
+    All,
+    #[serde(rename = "s")]
+    Server(i32),
+    #[serde(rename = "p")]
+    Active,
+}
+
+impl BroadcastTarget {
+    pub fn describe(self, fleet: &Fleet, lang: Lang) -> String {
+        match self {
+            BroadcastTarget::All => tr(lang, "broadcast_target_all"),
+            BroadcastTarget::Server(server_id) => {
+                let name = match fleet.node(server_id) {
+                    Ok(node) => node.server.name.clone(),
+                    Err(_) => server_id.to_string(),
+                };
+                trf(lang, "broadcast_target_server", &[("name", &name)])
+            }
+            BroadcastTarget::Active => trf(lang, "broadcast_target_active", &[("days", &ACTIVE_DAYS)]),
+        }
+    }
+
+    pub async fn recipients(self, storage: &StoragePtr) -> Result<Vec<UserId>> {
+        match self {
+            BroadcastTarget::All => storage.get_broadcast_recipients(None, None).await,
+            BroadcastTarget::Server(server_id) => {
+                storage.get_broadcast_recipients(Some(server_id), None).await
+            }
+            BroadcastTarget::Active => {
+                let since = Utc::now() - chrono::Duration::days(ACTIVE_DAYS);
+                storage.get_broadcast_recipients(None, Some(since)).await
+            }
+        }
+    }
+}
+
+/// Message of `from_chat` to be copied to every recipient. The report is
+/// sent back in `lang`.
+pub struct BroadcastJob {
+    pub from_chat: ChatId,
+    pub message_id: MessageId,
+    pub recipients: Vec<UserId>,
+    pub description: String,
+    pub lang: Lang,
+}
+
+#[derive(Debug, PartialEq)]
+enum Failure {
+    /// Flood control, the message can be sent again after the wait
+    RetryAfter(Duration),
+    Transient,
+    /// The user blocked the bot or deleted the account
+    Blocked,
+    Permanent,
+}
+
+fn classify(error: &RequestError) -> Failure {
+    match error {
+        RequestError::RetryAfter(wait) => Failure::RetryAfter(*wait),
+        RequestError::Network(_) | RequestError::Io(_) => Failure::Transient,
+        RequestError::Api(ApiError::BotBlocked | ApiError::UserDeactivated) => Failure::Blocked,
+        _ => Failure::Permanent,
+    }
+}
+
+#[derive(Debug, Default)]
+struct BroadcastReport {
+    delivered: usize,
+    blocked: usize,
+    failed: Vec<(UserId, String)>,
+}
+
+impl BroadcastReport {
+    fn render(&self, description: &str, lang: Lang) -> String {
+        let mut text = trf(
+            lang,
+            "broadcast_report",
+            &[
+                ("target", &description),
+                ("delivered", &self.delivered),
+                ("blocked", &self.blocked),
+                ("failed", &self.failed.len()),
+            ],
+        );
+        text.push('\n');
+        for (user_id, error) in self.failed.iter().take(MAX_REPORTED_FAILURES) {
+            text.push_str(&format!("{}: {}\n", user_id, error));
+        }
+        if self.failed.len() > MAX_REPORTED_FAILURES {
+            let more = self.failed.len() - MAX_REPORTED_FAILURES;
+            text.push_str(&format!("{}\n", trf(lang, "and_more", &[("count", &more)])));
+        }
+        text
+    }
+}
+
+/// Sends broadcasts one after another, so that even several of them stay
+/// within the rate limits, and reports each to the chat it came from.
+pub struct BroadcastQueue {
+    sender: mpsc::UnboundedSender<BroadcastJob>,
+}
+
+pub type BroadcastQueuePtr = Arc<BroadcastQueue>;
+
+impl BroadcastQueue {
+    pub fn start(bot: Bot, storage: StoragePtr) -> BroadcastQueuePtr {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<BroadcastJob>();
+        tokio::spawn(async move {
+            while let Some(job) = receiver.recv().await {
+                let report = send_job(&bot, &storage, &job).await;
+                let text = report.render(&job.description, job.lang);
+                if let Err(e) = bot.send_message(job.from_chat, text).send().await {
+                    tracing::warn!("Could not report broadcast to {}: {}", job.description, e);
+                }
+            }
+        });
+        Arc::new(Self { sender })
+    }
+
+    pub fn push(&self, job: BroadcastJob) -> Result<()> {
+        self.sender
+            .send(job)
+            .map_err(|_| anyhow::anyhow!("Broadcast queue is stopped"))
+    }
+}
+
+async fn send_job(bot: &Bot, storage: &StoragePtr, job: &BroadcastJob) -> BroadcastReport {
+    tracing::info!("Broadcasting to {} ({} users)", job.description, job.recipients.len());
+    let mut report = BroadcastReport::default();
+    for &user_id in &job.recipients {
+        match deliver(bot, job, user_id).await {
+            Ok(()) => report.delivered += 1,
+            Err((Failure::Blocked, _)) => {
+                report.blocked += 1;
+                if let Err(e) = storage.set_user_blocked(user_id, true).await {
+                    tracing::warn!("Could not flag user {} as blocked: {}", user_id, e);
+                }
+            }
+            Err((_, e)) => report.failed.push((user_id, e.to_string())),
+        }
+        tokio::time::sleep(SEND_INTERVAL).await;
+    }
+    report
+}
+
+async fn deliver(bot: &Bot, job: &BroadcastJob, user_id: UserId) -> Result<(), (Failure, RequestError)> {
+    let mut attempt = 1;
+    loop {
+        let error = match bot.copy_message(user_id, job.from_chat, job.message_id).send().await {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+        match classify(&error) {
+            Failure::RetryAfter(wait) => tokio::time::sleep(wait).await,
+            Failure::Transient if attempt < MAX_ATTEMPTS => {
+                tokio::time::sleep(RETRY_DELAY * attempt).await;
+                attempt += 1;
+            }
+            failure => return Err((failure, error)),
+        }
+    }
+}
+
+#[test]
+fn test_classify() {
+    let cases = vec![
+        (RequestError::RetryAfter(Duration::from_secs(7)), Failure::RetryAfter(Duration::from_secs(7))),
+        (RequestError::Io(std::io::ErrorKind::ConnectionReset.into()), Failure::Transient),
+        (RequestError::Api(ApiError::BotBlocked), Failure::Blocked),
+        (RequestError::Api(ApiError::UserDeactivated), Failure::Blocked),
+        (RequestError::Api(ApiError::ChatNotFound), Failure::Permanent),
+        (RequestError::MigrateToChatId(1), Failure::Permanent),
+    ];
+    for (error, failure) in cases {
+        assert_eq!(classify(&error), failure, "{}", error);
+    }
+}
+
+#[test]
+fn test_report_render() {
+    let report = BroadcastReport {
+        delivered: 40,
+        blocked: 2,
+        failed: (0..12).map(|i| (UserId(i), "Bad Request: chat not found".into())).collect(),
+    };
+    let text = report.render("users of nl1", Lang::En);
+    assert!(text.starts_with("Broadcast to users of nl1 finished\nDelivered: 40\nBlocked the bot: 2\nFailed: 12\n"));
+    assert!(text.contains("9: Bad Request") && !text.contains("10: Bad Request"));
+    assert!(text.ends_with("… and 2 more\n"));
+}
@@ -0,0 +1,214 @@
+use std::io::Cursor;
+
+use anyhow::Result;
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+
+use crate::statistics::Traffic;
+
+pub const WIDTH: u32 = 800;
+pub const HEIGHT: u32 = 400;
+const MARGIN: u32 = 20;
+
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+const GRID: Rgb<u8> = Rgb([225, 225, 225]);
+const AXIS: Rgb<u8> = Rgb([80, 80, 80]);
+pub const RX_COLOR: Rgb<u8> = Rgb([52, 120, 198]);
+pub const TX_COLOR: Rgb<u8> = Rgb([240, 140, 40]);
+
+/// Period a chart covers. Short names keep the callback data within
+/// Telegram's 64 bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ChartRange {
+    #[serde(rename = "d")]
+    Day,
+    #[serde(rename = "w")]
+    Week,
+    #[serde(rename = "m")]
+    Month,
+}
+
+impl ChartRange {
+    pub const ALL: [ChartRange; 3] = [ChartRange::Day, ChartRange::Week, ChartRange::Month];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ChartRange::Day => "day",
+            ChartRange::Week => "week",
+            ChartRange::Month => "month",
+        }
+    }
+
+    /// Bars of the chart, each covering `bucket()`.
+    pub fn buckets(self) -> usize {
+        match self {
+            ChartRange::Day => 24,
+            ChartRange::Week => 28,
+            ChartRange::Month => 30,
+        }
+    }
+
+    pub fn bucket(self) -> Duration {
+        match self {
+            ChartRange::Day => Duration::hours(1),
+            ChartRange::Week => Duration::hours(6),
+            ChartRange::Month => Duration::days(1),
+        }
+    }
+
+    /// Start of the first bucket, so that the last one ends with the current hour.
+    pub fn start(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let end = now.duration_trunc(Duration::hours(1)).unwrap_or(now) + Duration::hours(1);
+        end - self.bucket() * self.buckets() as i32
+    }
+}
+
+/// Sums hourly samples into the buckets of `range`. Samples outside of it are
+/// ignored, buckets without samples are empty.
+pub fn fill_buckets(
+    range: ChartRange,
+    now: DateTime<Utc>,
+    samples: &[(DateTime<Utc>, Traffic)],
+) -> Vec<Traffic> {
+    let start = range.start(now);
+    let bucket = range.bucket().num_seconds();
+    let mut buckets = vec![Traffic::default(); range.buckets()];
+    for (time, traffic) in samples {
+        let offset = (*time - start).num_seconds();
+        if offset < 0 {
+            continue;
+        }
+        if let Some(entry) = buckets.get_mut((offset / bucket) as usize) {
+            entry.rx += traffic.rx;
+            entry.tx += traffic.tx;
+        }
+    }
+    buckets
+}
+
+/// Bar chart of rx and tx per bucket, scaled to the largest value. Labels are
+/// left to the caption of the message.
+pub struct Chart {
+    pub buckets: Vec<Traffic>,
+}
+
+impl Chart {
+    pub fn draw(&self) -> RgbImage {
+        let mut image = RgbImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+        let (left, right, top, bottom) = (MARGIN, WIDTH - MARGIN, MARGIN, HEIGHT - MARGIN);
+        for i in 1..=4 {
+            let y = bottom - (bottom - top) * i / 4;
+            fill_rect(&mut image, left, y, right, y + 1, GRID);
+        }
+
+        let max = self.buckets.iter().map(|t| t.rx.max(t.tx)).max().unwrap_or(0);
+        if max > 0 {
+            for (i, traffic) in self.buckets.iter().enumerate() {
+                let (rx_x, tx_x, width) = self.bar_columns(i);
+                let rx = bar_height(traffic.rx, max, bottom - top);
+                let tx = bar_height(traffic.tx, max, bottom - top);
+                fill_rect(&mut image, rx_x, bottom - rx, rx_x + width, bottom, RX_COLOR);
+                fill_rect(&mut image, tx_x, bottom - tx, tx_x + width, bottom, TX_COLOR);
+            }
+        }
+        fill_rect(&mut image, left, bottom, right, bottom + 1, AXIS);
+        image
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        let mut png = Cursor::new(vec![]);
+        DynamicImage::ImageRgb8(self.draw()).write_to(&mut png, ImageOutputFormat::Png)?;
+        Ok(png.into_inner())
+    }
+
+    /// Left edges of the rx and tx bars of a bucket and their width.
+    fn bar_columns(&self, index: usize) -> (u32, u32, u32) {
+        let slot = (WIDTH - 2 * MARGIN) / self.buckets.len().max(1) as u32;
+        let width = (slot.saturating_sub(2) / 2).max(1);
+        let rx_x = MARGIN + slot * index as u32 + 1;
+        (rx_x, rx_x + width, width)
+    }
+}
+
+/// Non-zero values get at least a pixel, so that they are not lost next to
+/// large ones.
+fn bar_height(value: u64, max: u64, height: u32) -> u32 {
+    let scaled = (value as u128 * height as u128 / max as u128) as u32;
+    if value > 0 {
+        scaled.max(1)
+    } else {
+        0
+    }
+}
+
+fn fill_rect(image: &mut RgbImage, x0: u32, y0: u32, x1: u32, y1: u32, color: Rgb<u8>) {
+    for x in x0..x1.min(image.width()) {
+        for y in y0..y1.min(image.height()) {
+            image.put_pixel(x, y, color);
+        }
+    }
+}
+
+#[cfg(test)]
+fn column_height(image: &RgbImage, x: u32, color: Rgb<u8>) -> u32 {
+    (0..image.height()).filter(|&y| *image.get_pixel(x, y) == color).count() as u32
+}
+
+#[test]
+fn test_fill_buckets() {
+    let now = DateTime::parse_from_rfc3339("2023-04-10T12:30:00Z").unwrap().with_timezone(&Utc);
+    let hour = |h: i64| now.duration_trunc(Duration::hours(1)).unwrap() - Duration::hours(h);
+    let traffic = |rx, tx| Traffic { rx, tx };
+    let samples = vec![
+        (hour(0), traffic(1, 2)),
+        (hour(1), traffic(10, 20)),
+        (hour(5), traffic(100, 200)),
+        (hour(6), traffic(1000, 2000)),
+        // Older than a day
+        (hour(24), traffic(5, 5)),
+    ];
+
+    let day = fill_buckets(ChartRange::Day, now, &samples);
+    assert_eq!(day.len(), 24);
+    assert_eq!(day[23], traffic(1, 2));
+    assert_eq!(day[22], traffic(10, 20));
+    assert_eq!(day[18], traffic(100, 200));
+    assert_eq!(day[17], traffic(1000, 2000));
+    assert_eq!(day.iter().map(|t| t.rx).sum::<u64>(), 1111);
+
+    // Buckets of 6 hours, the last one ends with the current hour
+    let week = fill_buckets(ChartRange::Week, now, &samples);
+    assert_eq!(week.len(), 28);
+    assert_eq!(week[27], traffic(111, 222));
+    assert_eq!(week[26], traffic(1000, 2000));
+    assert_eq!(week[23], traffic(5, 5));
+}
+
+#[test]
+fn test_chart_png() {
+    let chart = Chart { buckets: vec![Traffic::default(); 30] };
+    let png = chart.to_png().unwrap();
+    let image = image::load_from_memory(&png).unwrap().to_rgb8();
+    assert_eq!((image.width(), image.height()), (WIDTH, HEIGHT));
+}
+
+#[test]
+fn test_chart_bars() {
+    let chart = Chart {
+        buckets: vec![
+            Traffic { rx: 400, tx: 100 },
+            Traffic { rx: 0, tx: 0 },
+            Traffic { rx: 1, tx: 200 },
+        ],
+    };
+    let image = chart.draw();
+    let plot = HEIGHT - 2 * MARGIN;
+    let heights: Vec<(u32, u32)> = (0..3)
+        .map(|i| {
+            let (rx_x, tx_x, _) = chart.bar_columns(i);
+            (column_height(&image, rx_x, RX_COLOR), column_height(&image, tx_x, TX_COLOR))
+        })
+        .collect();
+    assert_eq!(heights, vec![(plot, plot / 4), (0, 0), (1, plot / 2)]);
+}
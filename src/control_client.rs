@@ -1,62 +1,410 @@
 use crate::{
+    bot_metrics::BotMetricsPtr,
     rpc::wireguard::{
         wireguard_control_client::WireguardControlClient, SyncConfigRequest, Client, Server, StartWireguardRequest,
-        GetStatisticsRequest, GetStatisticsResponse,
+        GetStatisticsRequest, GetStatisticsResponse, StopWireguardRequest, RestartWireguardRequest,
+        GetInterfaceStatusRequest, GetInterfaceStatusResponse, UpdatePeersRequest, PreviewSyncResponse,
+        ConfigVersion, ListConfigVersionsRequest, ListConfigVersionsResponse, RollbackConfigRequest,
     },
-    storage::StoragePtr,
-    cfg::CfgPtr,
+    storage::{StoragePtr, VpnServer},
+    cfg::{read_auth_token, CfgPtr, ControlCfg},
     statistics::ClientEntry,
+    wireguard::diff::PeerDiff,
 };
 
+use std::{
+    fmt,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tonic_health::proto::{
+    health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+};
 use anyhow::Result;
+use opentelemetry::{global, propagation::Injector};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tonic::{
+    codegen::InterceptedService,
+    metadata::{Ascii, MetadataKey, MetadataMap, MetadataValue},
+    service::Interceptor,
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity},
+    Code, Request, Response, Status,
+};
+
+#[derive(Debug)]
+pub enum ControlError {
+    /// Control service could not be reached, a later call may succeed
+    Unreachable(Status),
+    /// Control service handled the request and returned an error
+    Rejected(Status),
+    /// Request could not be built on the bot side
+    InvalidRequest(anyhow::Error),
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreachable(status) => write!(f, "Control plane is unreachable: {}", status.message()),
+            Self::Rejected(status) => write!(f, "Control plane rejected request: {}", status.message()),
+            Self::InvalidRequest(e) => write!(f, "Could not build control plane request: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+impl From<Status> for ControlError {
+    fn from(status: Status) -> Self {
+        // Statuses returned by the server never carry a source, transport errors do
+        let transport_error = std::error::Error::source(&status).is_some();
+        match status.code() {
+            Code::Unavailable | Code::DeadlineExceeded | Code::Cancelled => Self::Unreachable(status),
+            Code::Unknown if transport_error => Self::Unreachable(status),
+            _ => Self::Rejected(status),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ControlError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::InvalidRequest(e)
+    }
+}
+
+/// Attaches the bearer token to every request if one is configured, and the
+/// trace context of the calling span.
+#[derive(Clone)]
+pub struct ClientInterceptor {
+    token: Option<MetadataValue<Ascii>>,
+}
+
+impl Interceptor for ClientInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.token {
+            request.metadata_mut().insert("authorization", token.clone());
+        }
+        inject_context(request.metadata_mut());
+        Ok(request)
+    }
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        let key = MetadataKey::from_bytes(key.as_bytes());
+        let value = value.parse::<MetadataValue<Ascii>>();
+        if let (Ok(key), Ok(value)) = (key, value) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+/// Adds the context of the current span to an outgoing gRPC request.
+fn inject_context(metadata: &mut MetadataMap) {
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut MetadataInjector(metadata))
+    });
+}
+
+type GrpcClient = WireguardControlClient<InterceptedService<Channel, ClientInterceptor>>;
+type GrpcHealthClient = HealthClient<InterceptedService<Channel, ClientInterceptor>>;
+
+const SERVICE_NAME: &str = "wireguard_control.WireguardControl";
+
+/// Client of one `wireguard_control` service. The channel is connected on
+/// first use and shared between all calls.
+pub struct ControlClient {
+    client: GrpcClient,
+    health: GrpcHealthClient,
+    retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    /// Name of the server, used as metrics label
+    server: String,
+    metrics: BotMetricsPtr,
+}
+
+pub type ControlClientPtr = Arc<ControlClient>;
+
+impl ControlClient {
+    /// Connects to the control service of `server` with the TLS, auth and
+    /// retry settings of `cfg`.
+    pub fn new(cfg: &ControlCfg, server: &VpnServer, metrics: BotMetricsPtr) -> Result<Self> {
+        let mut endpoint = Endpoint::from_shared(server.control_address.clone())?
+            .connect_timeout(humantime::parse_duration(&cfg.connect_timeout)?)
+            .timeout(humantime::parse_duration(&cfg.request_timeout)?);
+
+        if let Some(tls) = &cfg.tls {
+            let mut tls_config = ClientTlsConfig::new()
+                .ca_certificate(Certificate::from_pem(std::fs::read(&tls.ca_file)?));
+            if let (Some(cert_file), Some(key_file)) = (&tls.cert_file, &tls.key_file) {
+                tls_config = tls_config.identity(Identity::from_pem(
+                    std::fs::read(cert_file)?,
+                    std::fs::read(key_file)?,
+                ));
+            }
+            if let Some(domain) = &tls.domain {
+                tls_config = tls_config.domain_name(domain.clone());
+            }
+            endpoint = endpoint.tls_config(tls_config)?;
+        }
+
+        let token = read_auth_token(&cfg.auth_token, &cfg.auth_token_file)?
+            .map(|token| format!("Bearer {}", token).parse::<MetadataValue<Ascii>>())
+            .transpose()?;
+        let channel = endpoint.connect_lazy();
+        let interceptor = ClientInterceptor { token };
+        Ok(Self {
+            client: WireguardControlClient::with_interceptor(channel.clone(), interceptor.clone()),
+            health: HealthClient::with_interceptor(channel, interceptor),
+            retries: cfg.retries,
+            backoff: humantime::parse_duration(&cfg.backoff)?,
+            max_backoff: humantime::parse_duration(&cfg.max_backoff)?,
+            server: server.name.clone(),
+            metrics,
+        })
+    }
+
+    /// Retries `call` with exponential backoff while the service is unreachable.
+    #[tracing::instrument(skip_all, fields(server = %self.server, method = name))]
+    async fn call<C, T, F, Fut>(&self, client: &C, name: &str, call: F) -> Result<T, ControlError>
+    where
+        C: Clone,
+        F: Fn(C) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let started = Instant::now();
+        let result = self.call_with_retries(client, name, call).await;
+        self.metrics.observe_call(&self.server, name, started.elapsed(), &result);
+        result
+    }
+
+    async fn call_with_retries<C, T, F, Fut>(&self, client: &C, name: &str, call: F) -> Result<T, ControlError>
+    where
+        C: Clone,
+        F: Fn(C) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match call(client.clone()).await.map_err(ControlError::from) {
+                Ok(response) => return Ok(response.into_inner()),
+                Err(ControlError::Unreachable(status)) if attempt < self.retries => {
+                    attempt += 1;
+                    tracing::warn!(
+                        "{} failed (attempt {}/{}): {}. Retrying in {}",
+                        name, attempt, self.retries, status.message(), humantime::format_duration(backoff)
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = std::cmp::min(backoff * 2, self.max_backoff);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Waits until the control service is reachable and tells whether
+    /// `interface` is up.
+    pub async fn is_serving(&self, interface: &str) -> Result<bool, ControlError> {
+        let service = format!("{}/{}", SERVICE_NAME, interface);
+        let response = self
+            .call(&self.health, "HealthCheck", |mut client| {
+                let service = service.clone();
+                async move { client.check(HealthCheckRequest { service }).await }
+            })
+            .await?;
+        Ok(response.status == ServingStatus::Serving as i32)
+    }
+
+    #[tracing::instrument(skip_all, fields(server = %server.name))]
+    pub async fn sync_config(
+        &self,
+        storage: &StoragePtr,
+        cfg: &CfgPtr,
+        server: &VpnServer,
+    ) -> Result<(), ControlError> {
+        let started = Instant::now();
+        let request = SyncConfigRequest{
+            server: Some(get_server(storage, cfg, server).await?),
+            clients: get_clients(storage, server.id).await?,
+            interface: server.interface.clone(),
+        };
+        let _response = self
+            .call(&self.client, "SyncConfig", |mut client| {
+                let request = request.clone();
+                async move { client.sync_config(request).await }
+            })
+            .await?;
+        self.metrics
+            .sync_duration
+            .with_label_values(&[&self.server])
+            .observe(started.elapsed().as_secs_f64());
+        Ok(())
+    }
+
+    /// Shows what `sync_config` would change without applying it.
+    pub async fn preview_sync(
+        &self,
+        storage: &StoragePtr,
+        cfg: &CfgPtr,
+        server: &VpnServer,
+    ) -> Result<PreviewSyncResponse, ControlError> {
+        let request = SyncConfigRequest{
+            server: Some(get_server(storage, cfg, server).await?),
+            clients: get_clients(storage, server.id).await?,
+            interface: server.interface.clone(),
+        };
+        self.call(&self.client, "PreviewSync", |mut client| {
+            let request = request.clone();
+            async move { client.preview_sync(request).await }
+        })
+        .await
+    }
+
+    pub async fn start_wireguard_server(
+        &self,
+        storage: &StoragePtr,
+        cfg: &CfgPtr,
+        server: &VpnServer,
+    ) -> Result<(), ControlError> {
+        let request = StartWireguardRequest{
+            server: Some(get_server(storage, cfg, server).await?),
+            interface: server.interface.clone(),
+        };
+        let _ = self
+            .call(&self.client, "StartWireguard", |mut client| {
+                let request = request.clone();
+                async move { client.start_wireguard(request).await }
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn stop_wireguard_server(&self, interface: &str) -> Result<(), ControlError> {
+        let request = StopWireguardRequest{ interface: interface.to_owned() };
+        let _ = self
+            .call(&self.client, "StopWireguard", |mut client| {
+                let request = request.clone();
+                async move { client.stop_wireguard(request).await }
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Restarts the interface and pushes all peers back to it.
+    pub async fn restart_wireguard_server(
+        &self,
+        storage: &StoragePtr,
+        cfg: &CfgPtr,
+        server: &VpnServer,
+    ) -> Result<(), ControlError> {
+        let request = RestartWireguardRequest{
+            server: Some(get_server(storage, cfg, server).await?),
+            interface: server.interface.clone(),
+        };
+        let _ = self
+            .call(&self.client, "RestartWireguard", |mut client| {
+                let request = request.clone();
+                async move { client.restart_wireguard(request).await }
+            })
+            .await?;
+        self.sync_config(storage, cfg, server).await
+    }
+
+    pub async fn get_interface_status(&self, interface: &str) -> Result<GetInterfaceStatusResponse, ControlError> {
+        let request = GetInterfaceStatusRequest{ interface: interface.to_owned() };
+        self.call(&self.client, "GetInterfaceStatus", |mut client| {
+            let request = request.clone();
+            async move { client.get_interface_status(request).await }
+        })
+        .await
+    }
+
+    /// Applies `diff` to live peers instead of replacing the whole config.
+    pub async fn update_peers(&self, interface: &str, diff: &PeerDiff) -> Result<(), ControlError> {
+        let request = UpdatePeersRequest{
+            upsert: diff.add.iter().chain(diff.modify.iter()).cloned().collect(),
+            remove: diff.remove.iter().map(|c| c.key.clone()).collect(),
+            interface: interface.to_owned(),
+        };
+        let _ = self
+            .call(&self.client, "UpdatePeers", |mut client| {
+                let request = request.clone();
+                async move { client.update_peers(request).await }
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Applied configs kept by the control service, newest first.
+    pub async fn list_config_versions(&self, interface: &str) -> Result<Vec<ConfigVersion>, ControlError> {
+        let request = ListConfigVersionsRequest{ interface: interface.to_owned() };
+        let ListConfigVersionsResponse{ versions } = self
+            .call(&self.client, "ListConfigVersions", |mut client| {
+                let request = request.clone();
+                async move { client.list_config_versions(request).await }
+            })
+            .await?;
+        Ok(versions)
+    }
+
+    pub async fn rollback_config(&self, interface: &str, id: &str) -> Result<(), ControlError> {
+        let request = RollbackConfigRequest{ id: id.to_owned(), interface: interface.to_owned() };
+        let _ = self
+            .call(&self.client, "RollbackConfig", |mut client| {
+                let request = request.clone();
+                async move { client.rollback_config(request).await }
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_statistics(&self, interface: &str) -> Result<Vec<ClientEntry>, ControlError> {
+        let request = GetStatisticsRequest{ interface: interface.to_owned() };
+        let GetStatisticsResponse{ entries, warnings } = self
+            .call(&self.client, "GetStatistics", |mut client| {
+                let request = request.clone();
+                async move { client.get_statistics(request).await }
+            })
+            .await?;
+        for warning in warnings {
+            tracing::warn!("Statistics of {} are incomplete: {}", self.server, warning);
+        }
+        let entries: Vec<ClientEntry> = entries.into_iter().map(|e| e.into()).collect();
+        Ok(entries)
+    }
+}
+
+/// Peers of the server which should be configured according to the database.
+pub async fn get_clients(storage: &StoragePtr, server_id: i32) -> Result<Vec<Client>> {
+    Ok(storage.get_server_profiles(server_id).await?.into_iter()
+        .filter_map(|c| match c.ip {
+            std::net::IpAddr::V4(ip) => Some(Client { ip: ip.into(), key: c.public_key }),
+            _ => None,
+        })
+        .collect())
+}
 
-pub async fn sync_config(storage: &StoragePtr, cfg: &CfgPtr) -> Result<()> {
-    let mut client = WireguardControlClient::connect("http://wgc:8080").await?;
-    let request = SyncConfigRequest{
-        server: Some(Server {
-            key: cfg.private_key.clone(),
-            ip: std::net::Ipv4Addr::new(10, 9, 0, 1).into(),
-            port: 51820,
-            subnet: 24,
-            dns: vec![std::net::Ipv4Addr::new(8, 8, 8, 8).into()],
-            post_up: cfg.post_up.clone(),
-            pre_down: cfg.pre_down.clone(),
-        }),
-        clients: storage.get_profiles().await?.into_iter()
-            .map(|c| Some(Client{
-                ip: if let std::net::IpAddr::V4(ip) = c.ip { ip.into() } else { return None },
-                key: c.public_key
-            }))
-            .flatten()
-            .collect()
-    };
-    let _response = client.sync_config(request).await?;
-    Ok(())
-}
-
-
-pub async fn start_wireguard_server(cfg: &CfgPtr) -> Result<()> {
-    let mut client = WireguardControlClient::connect("http://wgc:8080").await?;
-    let request = StartWireguardRequest{
-        server: Some(Server {
-            key: cfg.private_key.clone(),
-            ip: std::net::Ipv4Addr::new(10, 9, 0, 1).into(),
-            port: 51820,
-            subnet: 24,
-            dns: vec![std::net::Ipv4Addr::new(8, 8, 8, 8).into()],
-            post_up: cfg.post_up.clone(),
-            pre_down: cfg.pre_down.clone(),
-        }),
-    };
-    let _ = client.start_wireguard(request).await?;
-    Ok(())
-}
-
-pub async fn get_statistics() -> Result<Vec<ClientEntry>> {
-    let mut client = WireguardControlClient::connect("http://wgc:8080").await?;
-    let request = GetStatisticsRequest{};
-    let response = client.get_statistics(request).await?; 
-    let GetStatisticsResponse{ entries } = response.into_inner();
-    let entries: Vec<ClientEntry> = entries.into_iter().map(|e| e.into()).collect();
-    Ok(entries)
+async fn get_server(storage: &StoragePtr, cfg: &CfgPtr, server: &VpnServer) -> Result<Server> {
+    let interface = cfg.interface(&server.interface)?;
+    let server_key = storage.get_active_server_key(server.id).await?;
+    let network = interface.network()?;
+    let dns = interface
+        .dns
+        .iter()
+        .map(|ip| Ok(ip.parse::<std::net::Ipv4Addr>()?.into()))
+        .collect::<Result<Vec<u32>>>()?;
+    Ok(Server {
+        key: server_key.private_key,
+        ip: network.addr().into(),
+        port: interface.port.into(),
+        subnet: network.prefix_len().into(),
+        dns,
+        post_up: interface.post_up.clone(),
+        pre_down: interface.pre_down.clone(),
+    })
 }
@@ -0,0 +1,219 @@
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use sha2::{Digest, Sha256};
+
+use crate::cfg::Cfg;
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+
+/// Key encryption key loaded from config. It never touches the database,
+/// only the per-row data keys wrapped with it do.
+pub struct MasterKey {
+    id: String,
+    cipher: ChaCha20Poly1305,
+}
+
+impl MasterKey {
+    pub fn from_base64(encoded: &str) -> Result<Self> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .context("Master key is not a valid base64 string")?;
+        if bytes.len() != KEY_SIZE {
+            return Err(anyhow!(
+                "Master key should be {} bytes long, not {}",
+                KEY_SIZE,
+                bytes.len()
+            ));
+        }
+        let digest = Sha256::digest(&bytes);
+        let id = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        Ok(Self {
+            id,
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&bytes)),
+        })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self> {
+        let encoded = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read master key file {}", path))?;
+        Self::from_base64(&encoded)
+    }
+
+    pub fn generate() -> String {
+        STANDARD.encode(ChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Private key encrypted with its own data key, plus that data key wrapped
+/// with a master key. Maps one to one onto the encrypted columns of `profiles`.
+#[derive(Debug, Clone)]
+pub struct SealedSecret {
+    pub master_key_id: String,
+    pub wrapped_data_key: Vec<u8>,
+    pub data_key_nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub nonce: Vec<u8>,
+}
+
+/// Current master key used for sealing and older ones still accepted for
+/// opening, so rows can be re-wrapped while the bot keeps running.
+pub struct Keyring {
+    current: MasterKey,
+    previous: Vec<MasterKey>,
+}
+
+impl Keyring {
+    pub fn new(current: MasterKey, previous: Vec<MasterKey>) -> Self {
+        Self { current, previous }
+    }
+
+    pub fn from_cfg(cfg: &Cfg) -> Result<Self> {
+        let current = match (&cfg.master_key, &cfg.master_key_file) {
+            (Some(key), _) => MasterKey::from_base64(key)?,
+            (None, Some(path)) => MasterKey::from_file(path)?,
+            (None, None) => {
+                return Err(anyhow!(
+                    "Either `master_key` or `master_key_file` should be set"
+                ))
+            }
+        };
+        let previous = cfg
+            .previous_master_key_files
+            .iter()
+            .map(|path| MasterKey::from_file(path))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(current, previous))
+    }
+
+    pub fn current_id(&self) -> &str {
+        self.current.id()
+    }
+
+    fn find(&self, id: &str) -> Result<&MasterKey> {
+        std::iter::once(&self.current)
+            .chain(self.previous.iter())
+            .find(|key| key.id == id)
+            .ok_or(anyhow!("Unknown master key {}", id))
+    }
+
+    /// `aad` binds the ciphertext to its row, so sealed values can't be
+    /// swapped between profiles.
+    pub fn seal(&self, plaintext: &str, aad: &[u8]) -> Result<SealedSecret> {
+        let data_key = ChaCha20Poly1305::generate_key(&mut OsRng);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&data_key)
+            .encrypt(&nonce, Payload { msg: plaintext.as_bytes(), aad })
+            .map_err(|_| anyhow!("Failed to encrypt secret"))?;
+
+        let data_key_nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let wrapped_data_key = self
+            .current
+            .cipher
+            .encrypt(&data_key_nonce, data_key.as_slice())
+            .map_err(|_| anyhow!("Failed to wrap data key"))?;
+
+        Ok(SealedSecret {
+            master_key_id: self.current.id.clone(),
+            wrapped_data_key,
+            data_key_nonce: data_key_nonce.to_vec(),
+            ciphertext,
+            nonce: nonce.to_vec(),
+        })
+    }
+
+    pub fn open(&self, sealed: &SealedSecret, aad: &[u8]) -> Result<String> {
+        let data_key = self.unwrap_data_key(sealed)?;
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&data_key))
+            .decrypt(
+                nonce_from_slice(&sealed.nonce)?,
+                Payload { msg: &sealed.ciphertext, aad },
+            )
+            .map_err(|_| anyhow!("Failed to decrypt secret"))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    /// Wraps the data key of `sealed` with the current master key. Returns
+    /// `None` if it is already wrapped with it. The ciphertext itself is kept.
+    pub fn rewrap(&self, sealed: &SealedSecret) -> Result<Option<SealedSecret>> {
+        if sealed.master_key_id == self.current.id {
+            return Ok(None);
+        }
+
+        let data_key = self.unwrap_data_key(sealed)?;
+        let data_key_nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let wrapped_data_key = self
+            .current
+            .cipher
+            .encrypt(&data_key_nonce, data_key.as_slice())
+            .map_err(|_| anyhow!("Failed to wrap data key"))?;
+
+        Ok(Some(SealedSecret {
+            master_key_id: self.current.id.clone(),
+            wrapped_data_key,
+            data_key_nonce: data_key_nonce.to_vec(),
+            ciphertext: sealed.ciphertext.clone(),
+            nonce: sealed.nonce.clone(),
+        }))
+    }
+
+    fn unwrap_data_key(&self, sealed: &SealedSecret) -> Result<Vec<u8>> {
+        let master_key = self.find(&sealed.master_key_id)?;
+        let data_key = master_key
+            .cipher
+            .decrypt(
+                nonce_from_slice(&sealed.data_key_nonce)?,
+                sealed.wrapped_data_key.as_slice(),
+            )
+            .map_err(|_| anyhow!("Failed to unwrap data key with master key {}", master_key.id))?;
+        if data_key.len() != KEY_SIZE {
+            return Err(anyhow!("Data key has invalid length {}", data_key.len()));
+        }
+        Ok(data_key)
+    }
+}
+
+fn nonce_from_slice(bytes: &[u8]) -> Result<&Nonce> {
+    if bytes.len() != NONCE_SIZE {
+        return Err(anyhow!("Nonce has invalid length {}", bytes.len()));
+    }
+    Ok(Nonce::from_slice(bytes))
+}
+
+#[test]
+fn test_seal_and_open() {
+    let keyring = Keyring::new(MasterKey::from_base64(&MasterKey::generate()).unwrap(), vec![]);
+    let sealed = keyring.seal("private key", b"public key").expect("Could not seal");
+    assert_eq!(sealed.master_key_id, keyring.current_id());
+    assert_eq!(keyring.open(&sealed, b"public key").unwrap(), "private key");
+    assert!(keyring.open(&sealed, b"another public key").is_err());
+}
+
+#[test]
+fn test_rewrap() {
+    let old = MasterKey::generate();
+    let new = MasterKey::generate();
+    let old_keyring = Keyring::new(MasterKey::from_base64(&old).unwrap(), vec![]);
+    let sealed = old_keyring.seal("private key", b"").unwrap();
+
+    let keyring = Keyring::new(
+        MasterKey::from_base64(&new).unwrap(),
+        vec![MasterKey::from_base64(&old).unwrap()],
+    );
+    let rewrapped = keyring.rewrap(&sealed).unwrap().expect("Should be rewrapped");
+    assert_eq!(rewrapped.master_key_id, keyring.current_id());
+    assert_eq!(rewrapped.ciphertext, sealed.ciphertext);
+    assert!(keyring.rewrap(&rewrapped).unwrap().is_none());
+
+    let new_only = Keyring::new(MasterKey::from_base64(&new).unwrap(), vec![]);
+    assert_eq!(new_only.open(&rewrapped, b"").unwrap(), "private key");
+    assert!(new_only.open(&sealed, b"").is_err());
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    bot_metrics::BotMetricsPtr,
+    cfg::CfgPtr,
+    control_client::{ControlClient, ControlError},
+    rpc::wireguard::Client,
+    storage::{Profile, StoragePtr, VpnServer},
+    wireguard::diff::PeerDiff,
+};
+
+/// VPN server together with the client of its control service.
+pub struct Node {
+    pub server: VpnServer,
+    pub client: ControlClient,
+}
+
+/// All VPN servers stored in the database, including disabled ones: their
+/// profiles still have to be synced.
+pub struct Fleet {
+    nodes: Vec<Node>,
+}
+
+pub type FleetPtr = Arc<Fleet>;
+
+impl Fleet {
+    pub async fn new(storage: &StoragePtr, cfg: &CfgPtr, metrics: &BotMetricsPtr) -> Result<Self> {
+        let nodes = storage
+            .get_servers()
+            .await?
+            .into_iter()
+            .map(|server| {
+                let client = ControlClient::new(&cfg.control, &server, metrics.clone())?;
+                Ok(Node { server, client })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { nodes })
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn node(&self, server_id: i32) -> Result<&Node> {
+        self.nodes
+            .iter()
+            .find(|node| node.server.id == server_id)
+            .ok_or(anyhow!("Could not find server {}", server_id))
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Node> {
+        self.nodes.iter().find(|node| node.server.name == name)
+    }
+
+    /// Servers offered to users for new profiles.
+    pub fn enabled(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter().filter(|node| node.server.enabled)
+    }
+
+    /// Syncs every server, stopping at the first failure.
+    pub async fn sync_all(&self, storage: &StoragePtr, cfg: &CfgPtr) -> Result<(), ControlError> {
+        for node in &self.nodes {
+            node.client.sync_config(storage, cfg, &node.server).await?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the server `profile` belongs to.
+    pub async fn sync_profile(
+        &self,
+        storage: &StoragePtr,
+        cfg: &CfgPtr,
+        profile: &Profile,
+    ) -> Result<(), ControlError> {
+        let node = self.node(profile.server_id)?;
+        node.client.sync_config(storage, cfg, &node.server).await
+    }
+
+    /// Moves the profile to another server. The peer is removed from the old
+    /// server before it is added to the new one, so the same key is never
+    /// served twice. Once the database is updated the move has happened, so
+    /// failing servers are only logged: the reconciler drops the stale peer
+    /// and adds the new one once they are back.
+    pub async fn move_profile(
+        &self,
+        storage: &StoragePtr,
+        cfg: &CfgPtr,
+        profile: &Profile,
+        target_id: i32,
+        keep_keys: bool,
+    ) -> Result<Profile> {
+        if profile.server_id == target_id {
+            return Err(anyhow!("Profile {} is already on this server", profile.name));
+        }
+        let source = self.node(profile.server_id)?;
+        let target = self.node(target_id)?;
+        if !target.server.enabled {
+            return Err(anyhow!("Server {} is disabled", target.server.name));
+        }
+
+        let interface = cfg.interface(&target.server.interface)?;
+        let moved = storage.move_profile(profile, &target.server, interface, keep_keys).await?;
+
+        let remove = PeerDiff {
+            remove: vec![peer(profile)?],
+            ..Default::default()
+        };
+        if let Err(e) = source.client.update_peers(&source.server.interface, &remove).await {
+            tracing::warn!(
+                "Could not remove peer {} from {}: {}",
+                profile.public_key, source.server.name, e
+            );
+        }
+
+        let add = PeerDiff {
+            add: vec![peer(&moved)?],
+            ..Default::default()
+        };
+        if let Err(e) = target.client.update_peers(&target.server.interface, &add).await {
+            tracing::warn!(
+                "Could not add peer {} to {}, the reconciler will add it: {}",
+                moved.public_key, target.server.name, e
+            );
+        }
+        tracing::info!(
+            "Profile {} moved from {} to {}",
+            profile.name, source.server.name, target.server.name
+        );
+        Ok(moved)
+    }
+}
+
+fn peer(profile: &Profile) -> Result<Client> {
+    match profile.ip {
+        std::net::IpAddr::V4(ip) => Ok(Client { key: profile.public_key.clone(), ip: ip.into() }),
+        std::net::IpAddr::V6(_) => Err(anyhow!("Invalid ip address of profile {}", profile.name)),
+    }
+}
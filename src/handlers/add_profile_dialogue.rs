@@ -6,54 +6,60 @@ use teloxide::{
     prelude::*,
 };
 
-use crate::{cfg::CfgPtr, control_client, storage::StoragePtr};
+use crate::{
+    cfg::CfgPtr,
+    fleet::FleetPtr,
+    i18n::{tr, trf, Lang},
+    storage::StoragePtr,
+};
 
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub enum AddProfileDialogueState {
     #[default]
     NotStarted,
-    WaitForName,
+    WaitForName { server_id: i32 },
 }
 
 pub type AddProfileDialogue =
     Dialogue<AddProfileDialogueState, InMemStorage<AddProfileDialogueState>>;
 
+#[tracing::instrument(skip_all, fields(chat_id = %msg.chat.id, server_id))]
 pub async fn handle_wait_for_name(
     bot: Bot,
     msg: Message,
     storage: StoragePtr,
     cfg: CfgPtr,
+    fleet: FleetPtr,
     add_profile_dialogue_storage: Arc<InMemStorage<AddProfileDialogueState>>,
+    server_id: i32,
 ) -> Result<()> {
+    let user_id = UserId(msg.chat.id.0 as u64);
+    let lang = Lang::of(&storage.get_user(user_id).await?);
     let name = msg.text().unwrap_or_default().to_owned();
     if name.is_empty() {
-        bot.send_message(msg.chat.id, "You should send profile name to create")
+        bot.send_message(msg.chat.id, tr(lang, "profile_name_empty"))
             .send()
             .await?;
         return Ok(());
     }
 
+    let node = fleet.node(server_id)?;
+    let interface = cfg.interface(&node.server.interface)?;
     if let Ok(_) = storage
-        .add_profile(&name, UserId(msg.chat.id.0 as u64))
+        .add_profile(&name, user_id, &node.server, interface)
         .await
     {
         add_profile_dialogue_storage
             .remove_dialogue(msg.chat.id)
             .await?;
-        control_client::sync_config(&storage, &cfg).await?;
-        bot.send_message(
-            msg.chat.id,
-            format!("Profile with name {} was created", name),
-        )
-        .send()
-        .await?;
+        node.client.sync_config(&storage, &cfg, &node.server).await?;
+        bot.send_message(msg.chat.id, trf(lang, "profile_created", &[("name", &name)]))
+            .send()
+            .await?;
     } else {
-        bot.send_message(
-            msg.chat.id,
-            format!("Profile with name {} is already exists", name),
-        )
-        .send()
-        .await?;
+        bot.send_message(msg.chat.id, trf(lang, "profile_exists", &[("name", &name)]))
+            .send()
+            .await?;
     }
     Ok(())
 }
@@ -1,19 +1,33 @@
+use std::{collections::HashMap, sync::Arc};
+
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use serde_json;
 use teloxide::{
+    dispatching::dialogue::InMemStorage,
     macros::BotCommands,
     prelude::*,
     types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode},
+    ApiError, RequestError,
 };
 
 use crate::{
-    handlers::user::get_process_error,
+    broadcast::{BroadcastJob, BroadcastQueuePtr, BroadcastTarget},
+    cfg::CfgPtr,
+    fleet::{Fleet, FleetPtr, Node},
+    charts::ChartRange,
+    i18n::{tr, trf, Lang},
+    handlers::{
+        broadcast_dialogue::{cancel_keyboard, BroadcastDialogue, BroadcastDialogueState},
+        statistics_view::{PeerReport, StatisticsSort},
+        user::{chart_keyboard, get_process_error, notify_profile_moved, render_session, send_traffic_chart},
+    },
+    key_rotation,
+    statistics::ONLINE_HANDSHAKE_AGE,
     storage::{StoragePtr, UserStatus},
-    control_client::get_statistics,
+    rpc::wireguard::PreviewSyncResponse,
 };
 
-#[derive(Default, Clone, BotCommands)]
+#[derive(Default, Clone, Debug, BotCommands)]
 #[command(rename_rule = "snake_case")]
 pub enum AdminCommands {
     #[default]
@@ -21,27 +35,75 @@ pub enum AdminCommands {
     NewInvite,
     RevokeInvites,
     ReviewRequests,
-    Statistics,
+    /// `[user id]`, live peers and a traffic chart of a user or everyone
+    Statistics {
+        user: String,
+    },
+    RotateServerKey,
+    RotationStatus,
+    Servers,
+    Online,
+    WgStatus {
+        server: String,
+    },
+    WgStart {
+        server: String,
+    },
+    WgStop {
+        server: String,
+    },
+    WgRestart {
+        server: String,
+    },
+    SyncPreview {
+        server: String,
+    },
+    ConfigVersions {
+        server: String,
+    },
+    /// `<server> [rekey]`, moves every profile of the server to the others
+    Evacuate {
+        args: String,
+    },
+    /// Sends a message to all users, users of a server or active users
+    Broadcast,
+}
+
+/// Servers an admin command is about, all of them if `name` is empty.
+fn select_nodes<'a>(fleet: &'a Fleet, name: &str) -> Result<Vec<&'a Node>> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Ok(fleet.nodes().iter().collect());
+    }
+    let node = fleet
+        .find(name)
+        .ok_or(anyhow::anyhow!("Could not find server {}", name))?;
+    Ok(vec![node])
 }
 
+#[tracing::instrument(skip_all, fields(chat_id = %msg.chat.id, command = ?cmd))]
 pub async fn on_command(
     bot: Bot,
     msg: Message,
     storage: StoragePtr,
+    cfg: CfgPtr,
+    fleet: FleetPtr,
+    broadcast_dialogue_storage: Arc<InMemStorage<BroadcastDialogueState>>,
     cmd: AdminCommands,
 ) -> Result<()> {
     let chat_id = msg.chat.id;
     let process_error = get_process_error(bot.clone(), chat_id);
+    let lang = Lang::of(&storage.get_user(UserId(chat_id.0 as u64)).await?);
     match cmd {
         AdminCommands::Admin => {
-            bot.send_message(chat_id, "Hi, admin!").send().await?;
+            bot.send_message(chat_id, tr(lang, "admin_hello")).send().await?;
         }
         AdminCommands::NewInvite => {
             let invite = storage
                 .create_invite_code()
                 .await
-                .map_err(process_error("Failed to create invite code".into()))?;
-            let text = format!("New invite code was created: `{}`", invite.id);
+                .map_err(process_error(tr(lang, "error_create_invite")))?;
+            let text = trf(lang, "invite_created", &[("code", &invite.id)]);
             bot.send_message(chat_id, text)
                 .parse_mode(ParseMode::MarkdownV2)
                 .send()
@@ -51,43 +113,31 @@ pub async fn on_command(
             storage
                 .revoke_all_invite_codes()
                 .await
-                .map_err(process_error("Failed to revoke all invite codes".into()))?;
-            bot.send_message(chat_id, "All invited codes were revoked")
+                .map_err(process_error(tr(lang, "error_revoke_invites")))?;
+            bot.send_message(chat_id, tr(lang, "invites_revoked"))
                 .send()
                 .await?;
         }
         AdminCommands::ReviewRequests => {
             let users = storage.get_users_with_requested_access().await?;
             if users.is_empty() {
-                bot.send_message(chat_id, "No requests").send().await?;
+                bot.send_message(chat_id, tr(lang, "no_requests")).send().await?;
                 return Ok(());
             }
 
-            let mut infos: Vec<(String, UserId)> = vec![];
-            for user in users {
-                let chat = bot.get_chat(ChatId(user.user_id.0 as i64)).await?;
-                let user_id = UserId(chat.id.0 as u64);
-                let name = format!(
-                    "{} @{} {}",
-                    chat.first_name().unwrap_or("(No first name)"),
-                    chat.username().unwrap_or("(No username)"),
-                    chat.last_name().unwrap_or("(No last name)")
-                );
-                infos.push((name, user_id));
-            }
-
             let mut text = String::new();
             let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
-            for (idx, (name, user_id)) in infos.into_iter().enumerate() {
+            for (idx, user) in users.into_iter().enumerate() {
+                let (name, user_id) = (user.display_name(), user.user_id);
                 text.push_str(&format!("{}. {}\n", idx + 1, name));
                 keyboard.push(vec![
                     InlineKeyboardButton::callback(
-                        format!("{}. Accept {}", idx + 1, name),
+                        trf(lang, "button_accept", &[("index", &(idx + 1)), ("name", &name)]),
                         serde_json::to_string(&AdminCallbackQuery::AcceptRequest { user_id })
                             .unwrap(),
                     ),
                     InlineKeyboardButton::callback(
-                        "Reject".to_string(),
+                        tr(lang, "button_reject"),
                         serde_json::to_string(&AdminCallbackQuery::RejectRequesst { user_id })
                             .unwrap(),
                     ),
@@ -99,41 +149,472 @@ pub async fn on_command(
                 .send()
                 .await?;
         },
-        AdminCommands::Statistics => {
-            let entries = get_statistics().await?;
-            let mut text = String::from("Statistics:\n");
-            for entry in entries {
-                let profile = storage.get_profile(&entry.pubkey).await?;
-                let chat = bot.get_chat(ChatId(profile.user_id.0 as i64)).await?;
-                let name = format!(
-                    "{} @{} {}",
-                    chat.first_name().unwrap_or("(No first name)"),
-                    chat.username().unwrap_or("(No username)"),
-                    chat.last_name().unwrap_or("(No last name)")
-                );
-                text.push_str(&format!("{} {}\n", name, entry));
+        AdminCommands::Statistics { user } => {
+            let user_id = match user.trim() {
+                "" => None,
+                user => Some(UserId(
+                    user.parse()
+                        .map_err(|e| anyhow::anyhow!("{}", e))
+                        .map_err(process_error(tr(lang, "invalid_user_id")))?,
+                )),
+            };
+            let (text, keyboard) =
+                render_statistics(&storage, &fleet, user_id, StatisticsSort::default(), 0, lang)
+                    .await
+                    .map_err(process_error(tr(lang, "error_statistics")))?;
+            bot.send_message(chat_id, text)
+                .reply_markup(keyboard)
+                .send()
+                .await?;
+        }
+        AdminCommands::RotateServerKey => {
+            let activate_at = key_rotation::start_rotation(&bot, &storage, &cfg, &fleet)
+                .await
+                .map_err(process_error(tr(lang, "error_start_rotation")))?;
+            let text = trf(lang, "rotation_started", &[("time", &activate_at.format("%Y-%m-%d %H:%M UTC"))]);
+            bot.send_message(chat_id, text).send().await?;
+        }
+        AdminCommands::RotationStatus => {
+            let report = key_rotation::build_report(&storage, &fleet)
+                .await
+                .map_err(process_error(tr(lang, "error_rotation_report")))?;
+            bot.send_message(chat_id, report).send().await?;
+        }
+        AdminCommands::Online => {
+            let text = render_online(&storage, &fleet, lang)
+                .await
+                .map_err(process_error(tr(lang, "error_sessions")))?;
+            bot.send_message(chat_id, text).send().await?;
+        }
+        AdminCommands::Servers => {
+            let text = render_servers(&storage, &fleet, lang).await?;
+            bot.send_message(chat_id, text).send().await?;
+        }
+        AdminCommands::WgStatus { server } => {
+            let nodes = select_nodes(&fleet, &server).map_err(process_error(tr(lang, "unknown_server")))?;
+            let mut text = String::new();
+            for node in nodes {
+                let name = &node.server.name;
+                let status = node
+                    .client
+                    .get_interface_status(&node.server.interface)
+                    .await
+                    .map_err(|e| process_error(tr(lang, "error_interface_status"))(e.into()))?;
+                let status = if status.exists {
+                    trf(
+                        lang,
+                        "wg_up",
+                        &[
+                            ("name", name),
+                            ("interface", &node.server.interface),
+                            ("port", &status.listen_port),
+                            ("key", &status.public_key),
+                            ("peers", &status.peer_count),
+                        ],
+                    )
+                } else {
+                    trf(lang, "wg_down", &[("name", name), ("interface", &node.server.interface)])
+                };
+                text.push_str(&format!("{}\n\n", status));
+            }
+            bot.send_message(chat_id, text).send().await?;
+        }
+        AdminCommands::WgStart { server } => {
+            let nodes = select_nodes(&fleet, &server).map_err(process_error(tr(lang, "unknown_server")))?;
+            for node in nodes {
+                let name = &node.server.name;
+                node.client
+                    .start_wireguard_server(&storage, &cfg, &node.server)
+                    .await
+                    .map_err(|e| process_error(trf(lang, "error_wg_start", &[("name", name)]))(e.into()))?;
+                node.client
+                    .sync_config(&storage, &cfg, &node.server)
+                    .await
+                    .map_err(|e| process_error(trf(lang, "error_wg_sync", &[("name", name)]))(e.into()))?;
+                bot.send_message(chat_id, trf(lang, "wg_started", &[("name", name)])).send().await?;
+            }
+        }
+        AdminCommands::WgStop { server } => {
+            let nodes = select_nodes(&fleet, &server).map_err(process_error(tr(lang, "unknown_server")))?;
+            for node in nodes {
+                let name = &node.server.name;
+                node.client
+                    .stop_wireguard_server(&node.server.interface)
+                    .await
+                    .map_err(|e| process_error(trf(lang, "error_wg_stop", &[("name", name)]))(e.into()))?;
+                bot.send_message(chat_id, trf(lang, "wg_stopped", &[("name", name)])).send().await?;
+            }
+        }
+        AdminCommands::SyncPreview { server } => {
+            let nodes = select_nodes(&fleet, &server).map_err(process_error(tr(lang, "unknown_server")))?;
+            for node in nodes {
+                let name = &node.server.name;
+                let preview = node
+                    .client
+                    .preview_sync(&storage, &cfg, &node.server)
+                    .await
+                    .map_err(|e| process_error(trf(lang, "error_sync_preview", &[("name", name)]))(e.into()))?;
+                let text = format!("{}:\n{}", name, render_sync_preview(&preview, lang));
+                bot.send_message(chat_id, text).send().await?;
+            }
+        }
+        AdminCommands::ConfigVersions { server } => {
+            let nodes = select_nodes(&fleet, &server).map_err(process_error(tr(lang, "unknown_server")))?;
+            for node in nodes {
+                let name = &node.server.name;
+                let versions = node
+                    .client
+                    .list_config_versions(&node.server.interface)
+                    .await
+                    .map_err(|e| process_error(tr(lang, "error_config_versions"))(e.into()))?;
+                if versions.is_empty() {
+                    bot.send_message(chat_id, trf(lang, "no_config_versions", &[("name", name)]))
+                        .send()
+                        .await?;
+                    continue;
+                }
+
+                let keyboard: Vec<Vec<InlineKeyboardButton>> = versions
+                    .iter()
+                    .map(|version| {
+                        let time = chrono::NaiveDateTime::from_timestamp_millis(version.timestamp as i64)
+                            .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                            .unwrap_or(version.id.clone());
+                        vec![InlineKeyboardButton::callback(
+                            trf(lang, "button_config_version", &[("time", &time), ("size", &version.size)]),
+                            serde_json::to_string(&AdminCallbackQuery::RollbackConfig {
+                                server_id: node.server.id,
+                                id: version.id.clone(),
+                            })
+                            .unwrap(),
+                        )]
+                    })
+                    .collect();
+                let text = trf(lang, "config_versions", &[("name", name)]);
+                bot.send_message(chat_id, text)
+                    .reply_markup(InlineKeyboardMarkup::new(keyboard))
+                    .send()
+                    .await?;
             }
+        }
+        AdminCommands::Evacuate { args } => {
+            let mut args = args.split_whitespace();
+            let name = args.next().unwrap_or_default();
+            let keep_keys = match args.next() {
+                None => true,
+                Some("rekey") => false,
+                Some(_) => {
+                    bot.send_message(chat_id, tr(lang, "evacuate_usage")).send().await?;
+                    return Ok(());
+                }
+            };
+            let source = fleet
+                .find(name)
+                .ok_or(anyhow::anyhow!("Could not find server {}", name))
+                .map_err(process_error(tr(lang, "unknown_server")))?;
+            let text = evacuate(&bot, &storage, &cfg, &fleet, source, keep_keys, lang)
+                .await
+                .map_err(process_error(trf(lang, "error_evacuate", &[("name", &name)])))?;
             bot.send_message(chat_id, text).send().await?;
         }
+        AdminCommands::Broadcast => {
+            BroadcastDialogue::new(broadcast_dialogue_storage, chat_id)
+                .update(BroadcastDialogueState::WaitForMessage)
+                .await?;
+            bot.send_message(chat_id, tr(lang, "broadcast_prompt"))
+                .reply_markup(cancel_keyboard(lang))
+                .send()
+                .await?;
+        }
+        AdminCommands::WgRestart { server } => {
+            let nodes = select_nodes(&fleet, &server).map_err(process_error(tr(lang, "unknown_server")))?;
+            for node in nodes {
+                let name = &node.server.name;
+                node.client
+                    .restart_wireguard_server(&storage, &cfg, &node.server)
+                    .await
+                    .map_err(|e| process_error(trf(lang, "error_wg_restart", &[("name", name)]))(e.into()))?;
+                bot.send_message(chat_id, trf(lang, "wg_restarted", &[("name", name)])).send().await?;
+            }
+        }
     }
     Ok(())
 }
 
+/// Moves every profile of `source` to the least loaded of the other enabled
+/// servers and asks their owners to import new configs.
+async fn evacuate(
+    bot: &Bot,
+    storage: &StoragePtr,
+    cfg: &CfgPtr,
+    fleet: &Fleet,
+    source: &Node,
+    keep_keys: bool,
+    lang: Lang,
+) -> Result<String> {
+    let mut load = vec![];
+    for node in fleet.enabled().filter(|node| node.server.id != source.server.id) {
+        load.push((node, storage.get_server_profiles(node.server.id).await?.len()));
+    }
+    if load.is_empty() {
+        return Err(anyhow::anyhow!(tr(lang, "no_other_servers")));
+    }
+
+    let mut moved = 0;
+    let mut failed = vec![];
+    for profile in storage.get_server_profiles(source.server.id).await? {
+        let (target, count) = load.iter_mut().min_by_key(|(_, count)| *count).unwrap();
+        match fleet.move_profile(storage, cfg, &profile, target.server.id, keep_keys).await {
+            Ok(profile) => {
+                *count += 1;
+                moved += 1;
+                if let Err(e) = notify_profile_moved(bot, storage, cfg, &profile, &target.server).await {
+                    tracing::warn!("Could not notify user {} about moved profile: {}", profile.user_id, e);
+                }
+            }
+            Err(e) => failed.push(trf(
+                lang,
+                "evacuate_failed_profile",
+                &[("name", &profile.name), ("user", &profile.user_id), ("error", &e)],
+            )),
+        }
+    }
+
+    let mut text = trf(lang, "evacuated", &[("count", &moved), ("name", &source.server.name)]);
+    text.push('\n');
+    if !failed.is_empty() {
+        text.push_str(&format!("\n{}\n", trf(lang, "evacuate_failed", &[("count", &failed.len())])));
+        failed.iter().for_each(|line| text.push_str(&format!("{}\n", line)));
+    }
+    Ok(text)
+}
+
+/// Page of the live peers with buttons to turn pages, change the order and
+/// draw a traffic chart.
+async fn render_statistics(
+    storage: &StoragePtr,
+    fleet: &Fleet,
+    user_id: Option<UserId>,
+    sort: StatisticsSort,
+    page: usize,
+    lang: Lang,
+) -> Result<(String, InlineKeyboardMarkup)> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let mut report = PeerReport::collect(storage, fleet, user_id).await?;
+    report.sort(sort);
+    let page = page.min(report.pages() - 1);
+    let text = report.render(page, sort, now, lang);
+
+    let button = |text: String, sort: StatisticsSort, page: usize| {
+        InlineKeyboardButton::callback(
+            text,
+            serde_json::to_string(&AdminCallbackQuery::StatisticsPage { user_id, sort, page }).unwrap(),
+        )
+    };
+    let mut navigation = vec![];
+    if page > 0 {
+        navigation.push(button(tr(lang, "button_prev"), sort, page - 1));
+    }
+    if page + 1 < report.pages() {
+        navigation.push(button(tr(lang, "button_next"), sort, page + 1));
+    }
+    let sorting = StatisticsSort::ALL
+        .iter()
+        .map(|&option| {
+            let label = tr(lang, option.key());
+            let label = if option == sort { format!("• {}", label) } else { label };
+            button(label, option, 0)
+        })
+        .collect();
+    let mut keyboard = vec![navigation, sorting];
+    keyboard.extend(
+        chart_keyboard(lang, |range| {
+            serde_json::to_string(&AdminCallbackQuery::StatisticsChart { user_id, range }).unwrap()
+        })
+        .inline_keyboard,
+    );
+    keyboard.retain(|row| !row.is_empty());
+    Ok((text, InlineKeyboardMarkup::new(keyboard)))
+}
+
+/// Rows shown by `/online`, so that the message stays within Telegram's limit.
+const MAX_ONLINE_ROWS: usize = 50;
+
+/// Peers with an open session, as of the latest statistics collection.
+async fn render_online(storage: &StoragePtr, fleet: &Fleet, lang: Lang) -> Result<String> {
+    let sessions = storage.get_open_sessions(None).await?;
+    if sessions.is_empty() {
+        return Ok(tr(lang, "nobody_online"));
+    }
+    let names: HashMap<UserId, String> = storage
+        .get_users()
+        .await?
+        .into_iter()
+        .map(|user| (user.user_id, user.display_name()))
+        .collect();
+    let mut text = format!("{}\n\n", trf(lang, "online_count", &[("count", &sessions.len())]));
+    for session in sessions.iter().take(MAX_ONLINE_ROWS) {
+        let server = fleet
+            .node(session.server_id)
+            .map(|node| node.server.name.clone())
+            .unwrap_or_else(|_| session.server_id.to_string());
+        let user = names.get(&session.user_id).cloned().unwrap_or_else(|| session.user_id.to_string());
+        text.push_str(&trf(
+            lang,
+            "online_session",
+            &[
+                ("user", &user),
+                ("profile", &session.profile),
+                ("server", &server),
+                ("session", &render_session(session, lang)),
+            ],
+        ));
+        text.push('\n');
+    }
+    if sessions.len() > MAX_ONLINE_ROWS {
+        let more = sessions.len() - MAX_ONLINE_ROWS;
+        text.push_str(&format!("{}\n", trf(lang, "and_more", &[("count", &more)])));
+    }
+    Ok(text)
+}
+
+/// Load of every server: stored profiles against live and recently active peers.
+async fn render_servers(storage: &StoragePtr, fleet: &Fleet, lang: Lang) -> Result<String> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let mut text = String::new();
+    for node in fleet.nodes() {
+        let server = &node.server;
+        let profiles = storage.get_server_profiles(server.id).await?.len();
+        text.push_str(&format!(
+            "{} {} ({}){}\n{}\n",
+            server.name,
+            server.region,
+            server.interface,
+            if server.enabled { String::new() } else { format!(", {}", tr(lang, "disabled")) },
+            trf(lang, "server_profiles", &[("count", &profiles)])
+        ));
+        match node.client.get_statistics(&server.interface).await {
+            Ok(entries) => {
+                let active = entries
+                    .iter()
+                    .filter(|e| e.latest_handshake > 0 && now.saturating_sub(e.latest_handshake) < ONLINE_HANDSHAKE_AGE)
+                    .count();
+                let tx: u64 = entries.iter().map(|e| e.tx).sum();
+                let rx: u64 = entries.iter().map(|e| e.rx).sum();
+                text.push_str(&trf(
+                    lang,
+                    "server_peers",
+                    &[("count", &entries.len()), ("active", &active), ("tx", &tx), ("rx", &rx)],
+                ));
+                text.push_str("\n\n");
+            }
+            Err(e) => text.push_str(&format!("{}\n\n", trf(lang, "server_unavailable", &[("error", &e)]))),
+        }
+    }
+    if text.is_empty() {
+        text.push_str(&tr(lang, "no_servers"));
+    }
+    Ok(text)
+}
+
+fn render_sync_preview(preview: &PreviewSyncResponse, lang: Lang) -> String {
+    let ip = |ip: u32| std::net::Ipv4Addr::from(ip).to_string();
+    let mut text = String::new();
+
+    if !preview.interface_changes.is_empty() {
+        text.push_str(&format!("{}\n", tr(lang, "sync_interface")));
+        for change in &preview.interface_changes {
+            text.push_str(&format!("  {}: {} -> {}\n", change.field, change.current, change.desired));
+        }
+    }
+    if !preview.peers_to_add.is_empty() {
+        let count = preview.peers_to_add.len();
+        text.push_str(&format!("{}\n", trf(lang, "sync_peers_to_add", &[("count", &count)])));
+        for peer in &preview.peers_to_add {
+            text.push_str(&format!("  {} {}\n", ip(peer.ip), peer.key));
+        }
+    }
+    if !preview.peers_to_remove.is_empty() {
+        let count = preview.peers_to_remove.len();
+        text.push_str(&format!("{}\n", trf(lang, "sync_peers_to_remove", &[("count", &count)])));
+        for peer in &preview.peers_to_remove {
+            text.push_str(&format!("  {} {}\n", ip(peer.ip), peer.key));
+        }
+    }
+    if !preview.peers_to_modify.is_empty() {
+        let count = preview.peers_to_modify.len();
+        text.push_str(&format!("{}\n", trf(lang, "sync_peers_to_modify", &[("count", &count)])));
+        for peer in &preview.peers_to_modify {
+            text.push_str(&format!("  {} {} -> {}\n", peer.key, ip(peer.current_ip), ip(peer.desired_ip)));
+        }
+    }
+
+    if text.is_empty() {
+        tr(lang, "sync_up_to_date")
+    } else {
+        text
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum AdminCallbackQuery {
     AcceptRequest { user_id: UserId },
     RejectRequesst { user_id: UserId },
+    /// Short names keep the callback data within Telegram's 64 bytes
+    #[serde(rename = "rollback")]
+    RollbackConfig {
+        #[serde(rename = "s")]
+        server_id: i32,
+        id: String,
+    },
+    #[serde(rename = "stats")]
+    StatisticsPage {
+        #[serde(rename = "u")]
+        user_id: Option<UserId>,
+        #[serde(rename = "o")]
+        sort: StatisticsSort,
+        #[serde(rename = "p")]
+        page: usize,
+    },
+    #[serde(rename = "chart")]
+    StatisticsChart {
+        #[serde(rename = "u")]
+        user_id: Option<UserId>,
+        #[serde(rename = "r")]
+        range: ChartRange,
+    },
+    #[serde(rename = "bc")]
+    Broadcast {
+        #[serde(rename = "t")]
+        target: BroadcastTarget,
+    },
+    #[serde(rename = "bc_cancel")]
+    BroadcastCancel,
 }
 
-pub async fn on_callback_query(cq: CallbackQuery, bot: Bot, storage: StoragePtr) -> Result<()> {
+#[tracing::instrument(skip_all, fields(chat_id = %cq.from.id, callback = %super::callback_type(&cq.data)))]
+pub async fn on_callback_query(
+    cq: CallbackQuery,
+    bot: Bot,
+    storage: StoragePtr,
+    fleet: FleetPtr,
+    broadcast_dialogue_storage: Arc<InMemStorage<BroadcastDialogueState>>,
+    broadcast_queue: BroadcastQueuePtr,
+) -> Result<()> {
     let query = serde_json::from_str(&cq.data.unwrap())?;
+    let lang = Lang::of(&storage.get_user(cq.from.id).await?);
     match query {
         AdminCallbackQuery::AcceptRequest { user_id } => {
             storage
                 .update_user_status(user_id, UserStatus::Granted)
                 .await?;
-            bot.send_message(ChatId::from(user_id), "Access granted")
+            let user_lang = Lang::of(&storage.get_user(user_id).await?);
+            bot.send_message(ChatId::from(user_id), tr(user_lang, "access_granted"))
                 .send()
                 .await?;
         }
@@ -141,10 +622,76 @@ pub async fn on_callback_query(cq: CallbackQuery, bot: Bot, storage: StoragePtr)
             storage
                 .update_user_status(user_id, UserStatus::Restricted)
                 .await?;
-            bot.send_message(ChatId::from(user_id), "Go away")
+            let user_lang = Lang::of(&storage.get_user(user_id).await?);
+            bot.send_message(ChatId::from(user_id), tr(user_lang, "go_away"))
                 .send()
                 .await?;
         }
+        AdminCallbackQuery::RollbackConfig { server_id, id } => {
+            let chat_id = ChatId::from(cq.from.id);
+            let node = fleet.node(server_id)?;
+            let name = &node.server.name;
+            let text = match node.client.rollback_config(&node.server.interface, &id).await {
+                Ok(()) => trf(lang, "rolled_back", &[("name", name), ("version", &id)]),
+                Err(e) => {
+                    tracing::error!("Failed to roll back config of {} to {}: {}", name, id, e);
+                    trf(lang, "error_rollback", &[("name", name), ("version", &id), ("error", &e)])
+                }
+            };
+            bot.send_message(chat_id, text).send().await?;
+        }
+        AdminCallbackQuery::StatisticsPage { user_id, sort, page } => {
+            let (text, keyboard) = render_statistics(&storage, &fleet, user_id, sort, page, lang).await?;
+            if let Some(message) = cq.message {
+                let result = bot
+                    .edit_message_text(message.chat.id, message.id, text)
+                    .reply_markup(keyboard)
+                    .send()
+                    .await;
+                // Pressing the button of the current order changes nothing
+                match result {
+                    Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        AdminCallbackQuery::StatisticsChart { user_id, range } => {
+            let subject = match user_id {
+                Some(user_id) => trf(lang, "chart_subject_user", &[("user", &user_id)]),
+                None => tr(lang, "chart_subject_all"),
+            };
+            send_traffic_chart(&bot, ChatId::from(cq.from.id), &storage, user_id, None, &subject, range).await?;
+        }
+        AdminCallbackQuery::Broadcast { target } => {
+            let chat_id = ChatId::from(cq.from.id);
+            let dialogue = BroadcastDialogue::new(broadcast_dialogue_storage, chat_id);
+            let message_id = match dialogue.get().await? {
+                Some(BroadcastDialogueState::WaitForTarget { message_id }) => message_id,
+                _ => {
+                    bot.send_message(chat_id, tr(lang, "broadcast_expired"))
+                        .send()
+                        .await?;
+                    return Ok(());
+                }
+            };
+            let recipients = target.recipients(&storage).await?;
+            let description = target.describe(&fleet, lang);
+            let text = trf(
+                lang,
+                "broadcast_queued",
+                &[("target", &description), ("count", &recipients.len())],
+            );
+            broadcast_queue.push(BroadcastJob { from_chat: chat_id, message_id, recipients, description, lang })?;
+            dialogue.exit().await?;
+            bot.send_message(chat_id, text).send().await?;
+        }
+        AdminCallbackQuery::BroadcastCancel => {
+            let chat_id = ChatId::from(cq.from.id);
+            BroadcastDialogue::new(broadcast_dialogue_storage, chat_id)
+                .update(BroadcastDialogueState::NotStarted)
+                .await?;
+            bot.send_message(chat_id, tr(lang, "broadcast_cancelled")).send().await?;
+        }
     }
 
     Ok(())
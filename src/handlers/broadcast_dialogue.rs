@@ -0,0 +1,71 @@
+use anyhow::Result;
+use teloxide::{
+    dispatching::dialogue::{Dialogue, InMemStorage},
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId},
+};
+
+use crate::{
+    broadcast::BroadcastTarget,
+    fleet::FleetPtr,
+    handlers::admin::AdminCallbackQuery,
+    i18n::{tr, trf, Lang},
+    storage::StoragePtr,
+};
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub enum BroadcastDialogueState {
+    #[default]
+    NotStarted,
+    WaitForMessage,
+    /// Preview of the message was shown, waiting for the recipients
+    WaitForTarget { message_id: MessageId },
+}
+
+pub type BroadcastDialogue =
+    Dialogue<BroadcastDialogueState, InMemStorage<BroadcastDialogueState>>;
+
+pub fn cancel_keyboard(lang: Lang) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+        tr(lang, "button_cancel"),
+        serde_json::to_string(&AdminCallbackQuery::BroadcastCancel).unwrap(),
+    )]])
+}
+
+fn target_keyboard(fleet: &FleetPtr, lang: Lang) -> InlineKeyboardMarkup {
+    let button = |target: BroadcastTarget| {
+        InlineKeyboardButton::callback(
+            trf(lang, "button_send_to", &[("target", &target.describe(fleet, lang))]),
+            serde_json::to_string(&AdminCallbackQuery::Broadcast { target }).unwrap(),
+        )
+    };
+    let mut keyboard = vec![vec![button(BroadcastTarget::All)], vec![button(BroadcastTarget::Active)]];
+    for node in fleet.nodes() {
+        keyboard.push(vec![button(BroadcastTarget::Server(node.server.id))]);
+    }
+    keyboard.extend(cancel_keyboard(lang).inline_keyboard);
+    InlineKeyboardMarkup::new(keyboard)
+}
+
+/// Shows the message as users will get it, with the choice of recipients.
+#[tracing::instrument(skip_all, fields(chat_id = %msg.chat.id))]
+pub async fn handle_wait_for_message(
+    bot: Bot,
+    msg: Message,
+    storage: StoragePtr,
+    fleet: FleetPtr,
+    dialogue: BroadcastDialogue,
+) -> Result<()> {
+    let lang = Lang::of(&storage.get_user(UserId(msg.chat.id.0 as u64)).await?);
+    bot.send_message(msg.chat.id, tr(lang, "broadcast_preview"))
+        .send()
+        .await?;
+    bot.copy_message(msg.chat.id, msg.chat.id, msg.id)
+        .reply_markup(target_keyboard(&fleet, lang))
+        .send()
+        .await?;
+    dialogue
+        .update(BroadcastDialogueState::WaitForTarget { message_id: msg.id })
+        .await?;
+    Ok(())
+}
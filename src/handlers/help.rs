@@ -0,0 +1,121 @@
+use anyhow::Result;
+use teloxide::{
+    prelude::*,
+    types::{BotCommand, BotCommandScope, Recipient},
+    utils::command::BotCommands,
+};
+
+use crate::{
+    cfg::Cfg,
+    handlers::{admin::AdminCommands, user::UserCommands},
+    i18n::{tr, Lang},
+    storage::{StoragePtr, UserStatus},
+};
+
+/// Names of the commands of `C`, without the slash.
+fn command_names<C: BotCommands>() -> Vec<String> {
+    C::bot_commands()
+        .into_iter()
+        .map(|command| command.command.trim_start_matches('/').to_owned())
+        .collect()
+}
+
+/// Whether a user command does anything for a user with `status`.
+fn is_available(command: &str, status: &UserStatus) -> bool {
+    match command {
+        "usage" | "notifications" => matches!(status, UserStatus::Granted),
+        "invite" => !matches!(status, UserStatus::Granted | UserStatus::Restricted),
+        _ => true,
+    }
+}
+
+/// Commands with their descriptions in `lang`, as the menu shows them.
+fn describe(names: &[String], lang: Lang) -> Vec<BotCommand> {
+    names
+        .iter()
+        .map(|name| BotCommand::new(name.clone(), tr(lang, &format!("command_{}", name))))
+        .collect()
+}
+
+fn render_list(text: &mut String, commands: &[BotCommand]) {
+    for command in commands {
+        text.push_str(&format!("/{} - {}\n", command.command, command.description));
+    }
+}
+
+pub fn render_help(status: &UserStatus, is_admin: bool, lang: Lang) -> String {
+    let names: Vec<String> = command_names::<UserCommands>()
+        .into_iter()
+        .filter(|name| is_available(name, status))
+        .collect();
+    let mut text = format!("{}\n", tr(lang, "help_commands"));
+    render_list(&mut text, &describe(&names, lang));
+    if is_admin {
+        text.push_str(&format!("\n{}\n", tr(lang, "help_admin_commands")));
+        render_list(&mut text, &describe(&command_names::<AdminCommands>(), lang));
+    }
+    text
+}
+
+/// Menu of the admin's chat: the user commands and then the admin ones, in
+/// the language the admin chose. Telegram shows it instead of the default
+/// menu there.
+pub async fn register_admin_commands(bot: &Bot, cfg: &Cfg, lang: Lang) -> Result<()> {
+    let mut names = command_names::<UserCommands>();
+    names.extend(command_names::<AdminCommands>());
+    bot.set_my_commands(describe(&names, lang))
+        .scope(BotCommandScope::Chat { chat_id: Recipient::Id(ChatId(cfg.admin_id)) })
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Sets the command menus: user commands for everyone in each language,
+/// English for clients in other languages, and the admin's own menu.
+pub async fn register_commands(bot: &Bot, storage: &StoragePtr, cfg: &Cfg) -> Result<()> {
+    let names = command_names::<UserCommands>();
+    bot.set_my_commands(describe(&names, Lang::default()))
+        .scope(BotCommandScope::Default)
+        .send()
+        .await?;
+    for lang in Lang::ALL {
+        bot.set_my_commands(describe(&names, lang))
+            .scope(BotCommandScope::Default)
+            .language_code(lang.code())
+            .send()
+            .await?;
+    }
+    let admin = storage.get_user(UserId(cfg.admin_id as u64)).await?;
+    register_admin_commands(bot, cfg, Lang::of(&admin)).await
+}
+
+#[test]
+fn test_commands_are_described() {
+    let mut names = command_names::<UserCommands>();
+    names.extend(command_names::<AdminCommands>());
+    assert!(names.contains(&"help".to_owned()));
+    for lang in Lang::ALL {
+        for command in describe(&names, lang) {
+            assert!(
+                !command.description.starts_with("command_"),
+                "Command {} has no description in locale {}",
+                command.command,
+                lang.code()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_render_help() {
+    let requested = render_help(&UserStatus::Requested, false, Lang::En);
+    assert!(requested.contains("/invite ") && !requested.contains("/usage "));
+    assert!(!requested.contains("/broadcast "));
+
+    let granted = render_help(&UserStatus::Granted, false, Lang::En);
+    assert!(granted.contains("/usage ") && granted.contains("/help ") && !granted.contains("/invite "));
+
+    let admin = render_help(&UserStatus::Granted, true, Lang::En);
+    assert!(admin.starts_with(&granted));
+    assert!(admin.contains("/broadcast ") && admin.contains("/wg_status "));
+}
@@ -1,5 +1,8 @@
 mod add_profile_dialogue;
 mod admin;
+mod broadcast_dialogue;
+mod help;
+mod statistics_view;
 mod user;
 
 use std::sync::Arc;
@@ -12,11 +15,35 @@ use teloxide::{
 
 use crate::{
     cfg::CfgPtr,
-    handlers::add_profile_dialogue::{handle_wait_for_name, AddProfileDialogue},
+    handlers::{
+        add_profile_dialogue::{handle_wait_for_name, AddProfileDialogue},
+        broadcast_dialogue::handle_wait_for_message,
+    },
     storage::StoragePtr,
 };
 
 pub use add_profile_dialogue::AddProfileDialogueState;
+pub use broadcast_dialogue::BroadcastDialogueState;
+pub use help::register_commands;
+pub use user::{format_bytes, new_config_keyboard};
+
+/// Value of the `type` tag of callback data, for spans.
+fn callback_type(data: &Option<String>) -> String {
+    data.as_deref()
+        .and_then(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .and_then(|value| value.get("type")?.as_str().map(str::to_owned))
+        .unwrap_or_default()
+}
+
+/// Keeps the Telegram profile of whoever sent the update up to date, so that
+/// views never have to ask Telegram for names.
+async fn track_user(update: Update, storage: StoragePtr) {
+    if let Some(user) = update.user() {
+        if let Err(e) = storage.touch_user(user).await {
+            tracing::warn!("Could not update user {}: {}", user.id, e);
+        }
+    }
+}
 
 pub fn get_handler(
     cfg: CfgPtr,
@@ -51,9 +78,20 @@ pub fn get_handler(
         .enter_dialogue::<Message, InMemStorage<AddProfileDialogueState>, AddProfileDialogueState>()
         .branch(
             dptree::filter_async(filter_non_empty_add_profile_dialogue).branch(
-                dptree::case![AddProfileDialogueState::WaitForName].endpoint(handle_wait_for_name),
+                dptree::case![AddProfileDialogueState::WaitForName { server_id }]
+                    .endpoint(handle_wait_for_name),
             ),
         )
+        // Commands are not broadcast, so that the admin can still use them
+        .branch(
+            dptree::filter(move |msg: Message| {
+                msg.chat.is_private()
+                    && msg.chat.id == admin_id
+                    && !msg.text().unwrap_or_default().starts_with('/')
+            })
+            .enter_dialogue::<Message, InMemStorage<BroadcastDialogueState>, BroadcastDialogueState>()
+            .branch(dptree::case![BroadcastDialogueState::WaitForMessage].endpoint(handle_wait_for_message)),
+        )
         .branch(
             dptree::entry()
                 .filter_command::<user::UserCommands>()
@@ -85,6 +123,7 @@ pub fn get_handler(
         );
 
     dptree::entry()
+        .inspect_async(track_user)
         .branch(msg_handler)
         .branch(callback_query_handler)
 }
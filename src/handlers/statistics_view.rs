@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use teloxide::prelude::*;
+
+use crate::{
+    fleet::Fleet,
+    handlers::user::format_bytes,
+    i18n::{tr, trf, Lang},
+    statistics::{ClientEntry, ONLINE_HANDSHAKE_AGE},
+    storage::StoragePtr,
+};
+
+pub const PAGE_SIZE: usize = 20;
+/// Longer user and profile names are cut, so that a page always fits in a message.
+const MAX_NAME_LEN: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum StatisticsSort {
+    #[default]
+    #[serde(rename = "t")]
+    Traffic,
+    #[serde(rename = "h")]
+    Handshake,
+    #[serde(rename = "n")]
+    Name,
+}
+
+impl StatisticsSort {
+    pub const ALL: [StatisticsSort; 3] = [
+        StatisticsSort::Traffic,
+        StatisticsSort::Handshake,
+        StatisticsSort::Name,
+    ];
+
+    /// Message key of the name of the order.
+    pub fn key(self) -> &'static str {
+        match self {
+            StatisticsSort::Traffic => "sort_traffic",
+            StatisticsSort::Handshake => "sort_handshake",
+            StatisticsSort::Name => "sort_name",
+        }
+    }
+}
+
+/// Live peer with the profile it belongs to. Peers without a profile are
+/// orphans: they are left on a server but unknown to the database.
+#[derive(Debug)]
+pub struct PeerRow {
+    pub user: Option<String>,
+    pub profile: Option<String>,
+    pub server: String,
+    pub entry: ClientEntry,
+}
+
+/// Peers of all reachable servers, optionally of a single user.
+pub struct PeerReport {
+    pub rows: Vec<PeerRow>,
+    /// Servers which did not answer, with the error
+    pub unavailable: Vec<(String, String)>,
+}
+
+impl PeerReport {
+    pub async fn collect(storage: &StoragePtr, fleet: &Fleet, user_id: Option<UserId>) -> Result<Self> {
+        let profiles: HashMap<String, _> = storage
+            .get_profiles()
+            .await?
+            .into_iter()
+            .map(|profile| (profile.public_key.clone(), profile))
+            .collect();
+        let names: HashMap<UserId, String> = storage
+            .get_users()
+            .await?
+            .into_iter()
+            .map(|user| (user.user_id, user.display_name()))
+            .collect();
+        let mut report = PeerReport { rows: vec![], unavailable: vec![] };
+        for node in fleet.nodes() {
+            let entries = match node.client.get_statistics(&node.server.interface).await {
+                Ok(entries) => entries,
+                Err(e) => {
+                    report.unavailable.push((node.server.name.clone(), e.to_string()));
+                    continue;
+                }
+            };
+            for entry in entries {
+                let profile = profiles.get(&entry.pubkey);
+                let owner = profile.map(|profile| profile.user_id);
+                if user_id.is_some() && owner != user_id {
+                    continue;
+                }
+                let user = owner.map(|owner| names.get(&owner).cloned().unwrap_or_else(|| owner.to_string()));
+                report.rows.push(PeerRow {
+                    user,
+                    profile: profile.map(|profile| profile.name.clone()),
+                    server: node.server.name.clone(),
+                    entry,
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    pub fn sort(&mut self, sort: StatisticsSort) {
+        match sort {
+            StatisticsSort::Traffic => self
+                .rows
+                .sort_by_key(|row| std::cmp::Reverse(row.entry.rx + row.entry.tx)),
+            // Peers which never connected go last
+            StatisticsSort::Handshake => self
+                .rows
+                .sort_by_key(|row| std::cmp::Reverse(row.entry.latest_handshake)),
+            // Orphans go last
+            StatisticsSort::Name => self.rows.sort_by_key(|row| {
+                (
+                    row.user.is_none(),
+                    row.user.as_deref().unwrap_or_default().to_lowercase(),
+                    row.profile.as_deref().unwrap_or_default().to_lowercase(),
+                )
+            }),
+        }
+    }
+
+    pub fn pages(&self) -> usize {
+        self.rows.len().div_ceil(PAGE_SIZE).max(1)
+    }
+
+    /// Totals of all peers and the rows of `page`, which is clamped to the
+    /// existing pages.
+    pub fn render(&self, page: usize, sort: StatisticsSort, now: u64, lang: Lang) -> String {
+        let page = page.min(self.pages() - 1);
+        let online = self.rows.iter().filter(|row| is_online(&row.entry, now)).count();
+        let orphans = self.rows.iter().filter(|row| row.profile.is_none()).count();
+        let rx: u64 = self.rows.iter().map(|row| row.entry.rx).sum();
+        let tx: u64 = self.rows.iter().map(|row| row.entry.tx).sum();
+
+        let mut text = trf(
+            lang,
+            "statistics_totals",
+            &[
+                ("peers", &self.rows.len()),
+                ("online", &online),
+                ("orphans", &orphans),
+                ("rx", &format_bytes(rx)),
+                ("tx", &format_bytes(tx)),
+            ],
+        );
+        text.push('\n');
+        for (server, error) in &self.unavailable {
+            let error = truncate(error, 100);
+            text.push_str(&trf(lang, "statistics_unavailable", &[("server", server), ("error", &error)]));
+            text.push('\n');
+        }
+        text.push_str(&trf(
+            lang,
+            "statistics_page",
+            &[
+                ("sort", &tr(lang, sort.key()).to_lowercase()),
+                ("page", &(page + 1)),
+                ("pages", &self.pages()),
+            ],
+        ));
+        text.push_str("\n\n");
+
+        let rows = self.rows.iter().enumerate().skip(page * PAGE_SIZE).take(PAGE_SIZE);
+        for (idx, row) in rows {
+            let owner = match (&row.user, &row.profile) {
+                (Some(user), Some(profile)) => {
+                    format!("{} / {}", truncate(user, MAX_NAME_LEN), truncate(profile, MAX_NAME_LEN))
+                }
+                _ => trf(lang, "statistics_orphan", &[("key", &truncate(&row.entry.pubkey, 12))]),
+            };
+            let server = match &row.entry.endpoint {
+                Some(endpoint) => trf(lang, "statistics_server_from", &[("server", &row.server), ("endpoint", endpoint)]),
+                None => row.server.clone(),
+            };
+            let traffic = trf(
+                lang,
+                "traffic",
+                &[("rx", &format_bytes(row.entry.rx)), ("tx", &format_bytes(row.entry.tx))],
+            );
+            text.push_str(&format!(
+                "{}. {} ({}): {}, {}\n",
+                idx + 1,
+                owner,
+                server,
+                handshake(&row.entry, now, lang),
+                traffic
+            ));
+        }
+        if self.rows.is_empty() {
+            text.push_str(&tr(lang, "statistics_no_peers"));
+            text.push('\n');
+        }
+        text
+    }
+}
+
+fn is_online(entry: &ClientEntry, now: u64) -> bool {
+    entry.latest_handshake != 0 && now.saturating_sub(entry.latest_handshake) < ONLINE_HANDSHAKE_AGE
+}
+
+fn handshake(entry: &ClientEntry, now: u64, lang: Lang) -> String {
+    if entry.latest_handshake == 0 {
+        return tr(lang, "never_connected");
+    }
+    let age = std::time::Duration::from_secs(now.saturating_sub(entry.latest_handshake));
+    let state = tr(lang, if is_online(entry, now) { "online" } else { "offline" });
+    format!("{}, {}", state, trf(lang, "ago", &[("duration", &humantime::format_duration(age))]))
+}
+
+fn truncate(text: &str, len: usize) -> String {
+    if text.chars().count() <= len {
+        text.to_owned()
+    } else {
+        format!("{}…", text.chars().take(len - 1).collect::<String>())
+    }
+}
+
+#[cfg(test)]
+fn test_report(count: usize) -> PeerReport {
+    let rows = (0..count)
+        .map(|i| PeerRow {
+            user: if i % 5 == 4 { None } else { Some(format!("user {}", "x".repeat(100 + i))) },
+            profile: if i % 5 == 4 { None } else { Some(format!("profile {}", i)) },
+            server: "nl1".into(),
+            entry: ClientEntry {
+                pubkey: format!("key{}", i),
+                ip: std::net::Ipv4Addr::new(10, 0, 0, i as u8),
+                latest_handshake: if i % 3 == 0 { 0 } else { 1_000 + i as u64 },
+                tx: (i as u64 * 7919) % 1000 * 1_000_000,
+                rx: i as u64,
+                endpoint: Some(format!("[2001:db8::{:x}]:51820", i)),
+                allowed_ips: vec![format!("10.0.0.{}/32", i)],
+                persistent_keepalive: None,
+            },
+        })
+        .collect();
+    PeerReport { rows, unavailable: vec![("de1".into(), "connection refused".into())] }
+}
+
+#[test]
+fn test_sort() {
+    let mut report = test_report(12);
+    report.sort(StatisticsSort::Traffic);
+    let traffic: Vec<u64> = report.rows.iter().map(|row| row.entry.rx + row.entry.tx).collect();
+    assert!(traffic.windows(2).all(|pair| pair[0] >= pair[1]));
+
+    report.sort(StatisticsSort::Handshake);
+    assert_eq!(report.rows[0].entry.latest_handshake, 1_011);
+    assert_eq!(report.rows.last().unwrap().entry.latest_handshake, 0);
+
+    report.sort(StatisticsSort::Name);
+    assert!(report.rows[..10].iter().all(|row| row.user.is_some()));
+    assert!(report.rows[10..].iter().all(|row| row.user.is_none()));
+}
+
+#[test]
+fn test_render_pages() {
+    let report = test_report(45);
+    assert_eq!(report.pages(), 3);
+    let last = report.render(2, StatisticsSort::Traffic, 10_000, Lang::En);
+    assert!(last.contains("page 3/3"));
+    assert!(last.contains("41. ") && last.contains("45. ") && !last.contains("40. "));
+    assert!(last.contains("Peers: 45, online: 0, orphans: 9"));
+    assert!(last.contains("de1 is unavailable"));
+    // Out of range pages show the last one
+    assert_eq!(report.render(7, StatisticsSort::Traffic, 10_000, Lang::En), last);
+    for page in 0..report.pages() {
+        assert!(report.render(page, StatisticsSort::Traffic, 10_000, Lang::En).chars().count() < 4096);
+    }
+
+    assert_eq!(PeerReport { rows: vec![], unavailable: vec![] }.pages(), 1);
+}
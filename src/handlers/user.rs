@@ -7,6 +7,7 @@ use std::{
 use anyhow::{anyhow, Result};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use chrono::Utc;
 use teloxide::{
     dispatching::dialogue::{InMemStorage, Storage},
     macros::BotCommands,
@@ -14,15 +15,21 @@ use teloxide::{
     types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, ParseMode},
 };
 
-use super::AddProfileDialogueState;
+use super::{
+    help::{register_admin_commands, render_help},
+    AddProfileDialogueState,
+};
 use crate::{
     cfg::CfgPtr,
-    control_client::sync_config,
-    storage::{Invite, StoragePtr, UserStatus},
+    charts::{fill_buckets, Chart, ChartRange},
+    fleet::FleetPtr,
+    i18n::{tr, trf, Lang},
+    statistics::{endpoint_host, Traffic},
+    storage::{Invite, Profile, Session, StoragePtr, UserStatus, VpnServer},
     wireguard::config::{build_peer_config, PeerConfig},
 };
 
-#[derive(Default, Clone, BotCommands)]
+#[derive(Default, Clone, Debug, BotCommands)]
 #[command(rename_rule = "snake_case")]
 pub enum UserCommands {
     #[default]
@@ -31,6 +38,12 @@ pub enum UserCommands {
     Invite {
         id: String,
     },
+    Usage,
+    /// Toggles messages about profiles connecting and disconnecting
+    Notifications,
+    Language,
+    /// Commands available to the caller
+    Help,
 }
 
 pub fn get_process_error(
@@ -54,27 +67,31 @@ pub fn get_process_error(
     })
 }
 
+#[tracing::instrument(skip_all, fields(chat_id = %msg.chat.id, command = ?cmd))]
 pub async fn on_command(
     bot: Bot,
     msg: Message,
     storage: StoragePtr,
     cfg: CfgPtr,
+    fleet: FleetPtr,
     cmd: UserCommands,
 ) -> Result<()> {
     let chat_id = msg.chat.id;
     let user_id = UserId(chat_id.0 as u64);
     let process_error = get_process_error(bot.clone(), chat_id);
-    let user_status = storage.get_user_status(user_id).await?;
+    let user = storage.get_user(user_id).await?;
+    let lang = Lang::of(&user);
+    let user_status = user.status;
 
     match cmd {
         UserCommands::Start => match user_status {
             UserStatus::Granted => {
                 let keyboard: Vec<Vec<InlineKeyboardButton>> =
                     vec![vec![InlineKeyboardButton::callback(
-                        "Manage profiles".to_string(),
+                        tr(lang, "manage_profiles"),
                         serde_json::to_string(&UserCallbackQuery::ManageProfiles {}).unwrap(),
                     )]];
-                bot.send_message(chat_id, "Ready to go")
+                bot.send_message(chat_id, tr(lang, "ready_to_go"))
                     .reply_markup(InlineKeyboardMarkup::new(keyboard))
                     .send()
                     .await?;
@@ -82,49 +99,110 @@ pub async fn on_command(
             UserStatus::None => {
                 let keyboard: Vec<Vec<InlineKeyboardButton>> =
                     vec![vec![InlineKeyboardButton::callback(
-                        "Request acceess".to_string(),
+                        tr(lang, "request_access"),
                         serde_json::to_string(&UserCallbackQuery::RequestAccess {}).unwrap(),
                     )]];
-                bot.send_message(chat_id, "Access denied")
+                bot.send_message(chat_id, tr(lang, "access_denied"))
                     .reply_markup(InlineKeyboardMarkup::new(keyboard))
                     .send()
                     .await?;
             }
             _ => {
-                bot.send_message(chat_id, "Access denied").send().await?;
+                bot.send_message(chat_id, tr(lang, "access_denied")).send().await?;
                 return Ok(());
             }
         },
         UserCommands::ID => {
-            bot.send_message(msg.chat.id, format!("Your id: {}", msg.chat.id))
+            bot.send_message(msg.chat.id, trf(lang, "your_id", &[("id", &msg.chat.id)]))
                 .send()
                 .await?;
         }
         UserCommands::Invite { id } => match user_status {
             UserStatus::Granted => {
-                bot.send_message(chat_id, "You are already has access")
+                bot.send_message(chat_id, tr(lang, "already_granted"))
                     .send()
                     .await?;
                 return Ok(());
             }
             UserStatus::Restricted => {
-                bot.send_message(chat_id, "Go away").send().await?;
+                bot.send_message(chat_id, tr(lang, "go_away")).send().await?;
                 return Ok(());
             }
             _ => {
                 let invite = Invite {
                     id: uuid::Uuid::try_parse(&id)
                         .map_err(|e| anyhow!(e))
-                        .map_err(process_error("Invalid id".into()))?
+                        .map_err(process_error(tr(lang, "invalid_invite")))?
                 };
                 storage
                     .activate_user(user_id, invite)
                     .await
-                    .map_err(process_error("Failed to apply invite".into()))?;
+                    .map_err(process_error(tr(lang, "error_invite")))?;
 
-                bot.send_message(chat_id, "Access granted").send().await?;
+                bot.send_message(chat_id, tr(lang, "access_granted")).send().await?;
             }
         },
+        UserCommands::Usage => {
+            if !matches!(user_status, UserStatus::Granted) {
+                bot.send_message(chat_id, tr(lang, "access_denied")).send().await?;
+                return Ok(());
+            }
+            let profiles = storage
+                .get_user_profiles(user_id)
+                .await
+                .map_err(process_error(tr(lang, "error_get_profiles")))?;
+            if profiles.is_empty() {
+                bot.send_message(chat_id, tr(lang, "no_profiles"))
+                    .send()
+                    .await?;
+                return Ok(());
+            }
+            let text = render_usage(&storage, &cfg, &fleet, &profiles, lang)
+                .await
+                .map_err(process_error(tr(lang, "error_usage")))?;
+            let keyboard = chart_keyboard(lang, |range| {
+                serde_json::to_string(&UserCallbackQuery::UsageChart { name: None, range }).unwrap()
+            });
+            bot.send_message(chat_id, text)
+                .reply_markup(keyboard)
+                .send()
+                .await?;
+        }
+        UserCommands::Notifications => {
+            if !matches!(user_status, UserStatus::Granted) {
+                bot.send_message(chat_id, tr(lang, "access_denied")).send().await?;
+                return Ok(());
+            }
+            let enabled = !storage.get_user(user_id).await?.notify_sessions;
+            storage
+                .set_session_notifications(user_id, enabled)
+                .await
+                .map_err(process_error(tr(lang, "error_notifications")))?;
+            let text = tr(lang, if enabled { "notifications_on" } else { "notifications_off" });
+            bot.send_message(chat_id, text).send().await?;
+        }
+        UserCommands::Language => {
+            let buttons = Lang::ALL
+                .iter()
+                .map(|&option| {
+                    let label = if option == lang { format!("• {}", option.name()) } else { option.name().into() };
+                    InlineKeyboardButton::callback(
+                        label,
+                        serde_json::to_string(&UserCallbackQuery::SetLanguage { lang: option }).unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            bot.send_message(chat_id, tr(lang, "choose_language"))
+                .reply_markup(InlineKeyboardMarkup::new(vec![buttons]))
+                .send()
+                .await?;
+        }
+        UserCommands::Help => {
+            let is_admin = chat_id == ChatId(cfg.admin_id);
+            bot.send_message(chat_id, render_help(&user_status, is_admin, lang))
+                .send()
+                .await?;
+        }
     }
     Ok(())
 }
@@ -151,13 +229,51 @@ pub enum UserCallbackQuery {
         action: ManageProfileAction,
     },
     RequestAccess,
+    ChooseServer {
+        server_id: i32,
+    },
+    NewConfig {
+        name: String,
+        action: ManageProfileAction,
+        /// Built with the pending server key, for key rotation notices
+        #[serde(rename = "p")]
+        pending: bool,
+    },
+    ChooseMoveTarget {
+        name: String,
+    },
+    /// Short names keep the callback data within Telegram's 64 bytes
+    #[serde(rename = "move")]
+    MoveProfile {
+        name: String,
+        #[serde(rename = "s")]
+        server_id: i32,
+    },
+    ProfileUsage {
+        name: String,
+    },
+    /// Chart of a profile, or of all profiles of the user without a name
+    #[serde(rename = "chart")]
+    UsageChart {
+        #[serde(rename = "p")]
+        name: Option<String>,
+        #[serde(rename = "r")]
+        range: ChartRange,
+    },
+    #[serde(rename = "lang")]
+    SetLanguage {
+        #[serde(rename = "l")]
+        lang: Lang,
+    },
 }
 
+#[tracing::instrument(skip_all, fields(chat_id = %cq.from.id, callback = %super::callback_type(&cq.data)))]
 pub async fn on_callback_query(
     cq: CallbackQuery,
     bot: Bot,
     storage: StoragePtr,
     cfg: CfgPtr,
+    fleet: FleetPtr,
     add_profile_dialogue_storage: Arc<InMemStorage<AddProfileDialogueState>>,
 ) -> Result<()> {
     if let Some(data) = cq.data {
@@ -165,18 +281,18 @@ pub async fn on_callback_query(
         let process_error = get_process_error(bot.clone(), cq.from.id.into());
         let user_id = cq.from.id;
         let chat_id = ChatId::from(user_id);
-        let sync_server_config = || sync_config(&storage, &cfg);
+        let lang = Lang::of(&storage.get_user(user_id).await?);
 
         match callback_query {
             UserCallbackQuery::GetProfileManager { name } => {
                 let _ = storage
                     .get_user_profile(user_id, &name)
                     .await
-                    .map_err(process_error("Could not get user profile".into()))?;
+                    .map_err(process_error(tr(lang, "error_get_profile")))?;
 
-                let keyboard: Vec<Vec<InlineKeyboardButton>> = vec![
+                let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![
                     vec![InlineKeyboardButton::callback(
-                        "Delete profile",
+                        tr(lang, "button_delete_profile"),
                         serde_json::to_string(&UserCallbackQuery::ManageProfile {
                             name: name.clone(),
                             action: ManageProfileAction::Delete,
@@ -184,7 +300,7 @@ pub async fn on_callback_query(
                         .unwrap(),
                     )],
                     vec![InlineKeyboardButton::callback(
-                        "Get profile as text",
+                        tr(lang, "button_get_text"),
                         serde_json::to_string(&UserCallbackQuery::ManageProfile {
                             name: name.clone(),
                             action: ManageProfileAction::GetText,
@@ -192,7 +308,7 @@ pub async fn on_callback_query(
                         .unwrap(),
                     )],
                     vec![InlineKeyboardButton::callback(
-                        "... as QR",
+                        tr(lang, "button_get_qr"),
                         serde_json::to_string(&UserCallbackQuery::ManageProfile {
                             name: name.clone(),
                             action: ManageProfileAction::GetQR,
@@ -200,7 +316,7 @@ pub async fn on_callback_query(
                         .unwrap(),
                     )],
                     vec![InlineKeyboardButton::callback(
-                        "... as file",
+                        tr(lang, "button_get_file"),
                         serde_json::to_string(&UserCallbackQuery::ManageProfile {
                             name: name.clone(),
                             action: ManageProfileAction::GetFile,
@@ -208,10 +324,26 @@ pub async fn on_callback_query(
                         .unwrap(),
                     )],
                 ];
+                keyboard.push(vec![InlineKeyboardButton::callback(
+                    tr(lang, "button_usage"),
+                    serde_json::to_string(&UserCallbackQuery::ProfileUsage {
+                        name: name.clone(),
+                    })
+                    .unwrap(),
+                )]);
+                if fleet.enabled().count() > 1 {
+                    keyboard.push(vec![InlineKeyboardButton::callback(
+                        tr(lang, "button_move"),
+                        serde_json::to_string(&UserCallbackQuery::ChooseMoveTarget {
+                            name: name.clone(),
+                        })
+                        .unwrap(),
+                    )]);
+                }
                 bot.edit_message_text(
                     user_id,
                     cq.message.unwrap().id,
-                    format!("Manage profile {name}"),
+                    trf(lang, "manage_profile", &[("name", &name)]),
                 )
                 .reply_markup(InlineKeyboardMarkup::new(keyboard))
                 .send()
@@ -221,9 +353,9 @@ pub async fn on_callback_query(
                 let profiles = storage
                     .get_user_profiles(user_id)
                     .await
-                    .map_err(process_error("Could not fetch user profiles".into()))?;
+                    .map_err(process_error(tr(lang, "error_get_profiles")))?;
                 if profiles.is_empty() {
-                    bot.send_message(user_id, "There is no available profiles")
+                    bot.send_message(user_id, tr(lang, "no_profiles"))
                         .send()
                         .await?;
                     return Ok(());
@@ -246,7 +378,7 @@ pub async fn on_callback_query(
                     keyboard.push(row);
                 }
 
-                bot.edit_message_text(user_id, cq.message.unwrap().id, "Your profiles")
+                bot.edit_message_text(user_id, cq.message.unwrap().id, tr(lang, "your_profiles"))
                     .reply_markup(InlineKeyboardMarkup::new(keyboard))
                     .send()
                     .await?;
@@ -254,132 +386,235 @@ pub async fn on_callback_query(
             UserCallbackQuery::ManageProfiles => {
                 let keyboard: Vec<Vec<InlineKeyboardButton>> = vec![
                     vec![InlineKeyboardButton::callback(
-                        "Add profile",
+                        tr(lang, "button_add_profile"),
                         serde_json::to_string(&UserCallbackQuery::AddProfile {}).unwrap(),
                     )],
                     vec![InlineKeyboardButton::callback(
-                        "Get profile",
+                        tr(lang, "button_get_profile"),
                         serde_json::to_string(&UserCallbackQuery::ListProfiles {}).unwrap(),
                     )],
                 ];
 
-                bot.edit_message_text(user_id, cq.message.unwrap().id, "Manage profiles")
+                bot.edit_message_text(user_id, cq.message.unwrap().id, tr(lang, "manage_profiles"))
                     .reply_markup(InlineKeyboardMarkup::new(keyboard))
                     .send()
                     .await?;
             }
             UserCallbackQuery::AddProfile => {
+                let nodes: Vec<_> = fleet.enabled().collect();
+                if let [node] = nodes.as_slice() {
+                    let state = AddProfileDialogueState::WaitForName {
+                        server_id: node.server.id,
+                    };
+                    add_profile_dialogue_storage
+                        .update_dialogue(user_id.into(), state)
+                        .await?;
+                    bot.edit_message_text(user_id, cq.message.unwrap().id, tr(lang, "send_profile_name"))
+                        .send()
+                        .await?;
+                    return Ok(());
+                }
+
+                let keyboard: Vec<Vec<InlineKeyboardButton>> = nodes
+                    .iter()
+                    .map(|node| {
+                        vec![InlineKeyboardButton::callback(
+                            server_label(&node.server, &cfg),
+                            serde_json::to_string(&UserCallbackQuery::ChooseServer {
+                                server_id: node.server.id,
+                            })
+                            .unwrap(),
+                        )]
+                    })
+                    .collect();
+                bot.edit_message_text(user_id, cq.message.unwrap().id, tr(lang, "choose_location"))
+                    .reply_markup(InlineKeyboardMarkup::new(keyboard))
+                    .send()
+                    .await?;
+            }
+            UserCallbackQuery::ChooseServer { server_id } => {
+                let enabled = fleet.node(server_id).map(|node| node.server.enabled).unwrap_or(false);
+                if !enabled {
+                    bot.send_message(user_id, tr(lang, "location_unavailable"))
+                        .send()
+                        .await?;
+                    return Ok(());
+                }
                 add_profile_dialogue_storage
-                    .update_dialogue(user_id.into(), AddProfileDialogueState::WaitForName)
+                    .update_dialogue(user_id.into(), AddProfileDialogueState::WaitForName { server_id })
                     .await?;
-                bot.edit_message_text(user_id, cq.message.unwrap().id, "Send profile name")
+                bot.edit_message_text(user_id, cq.message.unwrap().id, tr(lang, "send_profile_name"))
                     .send()
                     .await?;
             }
             UserCallbackQuery::ManageProfile { name, action } => match action {
                 ManageProfileAction::Delete => {
+                    let profile = storage
+                        .get_user_profile(user_id, &name)
+                        .await
+                        .map_err(process_error(tr(lang, "error_get_profile")))?;
                     storage
                         .delete_user_profile(user_id, &name)
                         .await
-                        .map_err(process_error("Could not delete profile".into()))?;
-                    sync_server_config()
+                        .map_err(process_error(tr(lang, "error_delete_profile")))?;
+                    fleet
+                        .sync_profile(&storage, &cfg, &profile)
                         .await
-                        .map_err(process_error("Could not sync server config".into()))?;
-                    bot.send_message(
-                        user_id,
-                        format!("Profile with name {name} deleted successfully"),
-                    )
+                        .map_err(|e| process_error(tr(lang, "error_sync"))(e.into()))?;
+                    bot.send_message(user_id, trf(lang, "profile_deleted", &[("name", &name)]))
                     .send()
                     .await?;
                 }
-                ManageProfileAction::GetText => {
+                _ => {
                     let profile = storage
-                        .get_user_profile(user_id.into(), &name)
+                        .get_user_profile(user_id, &name)
                         .await
-                        .map_err(process_error("Could not get user profile".into()))?;
-
-                    let peer_cfg = PeerConfig::new(&profile, &cfg)
-                        .map_err(process_error("Could not build peer config".into()))?;
-                    let profile_text = build_peer_config(&peer_cfg)
-                        .map_err(|e| anyhow!(e))
-                        .map_err(process_error("Could not build client config".into()))?;
-
-                    bot.send_message(user_id, format!("Config:\n\n```\n{}\n```", profile_text))
-                        .parse_mode(ParseMode::MarkdownV2)
-                        .send()
-                        .await?;
-                }
-                ManageProfileAction::GetFile => {
-                    let profile = storage
-                        .get_user_profile(user_id.into(), &name)
+                        .map_err(process_error(tr(lang, "error_get_profile")))?;
+                    let server = &fleet.node(profile.server_id)?.server;
+                    let server_key = storage
+                        .get_active_server_key(server.id)
                         .await
-                        .map_err(process_error("Could not get user profile".into()))?;
+                        .map_err(process_error(tr(lang, "error_server_key")))?;
 
-                    let peer_cfg = PeerConfig::new(&profile, &cfg)?;
-                    let profile_text = build_peer_config(&peer_cfg)
-                        .map_err(|e| anyhow!(e))
-                        .map_err(process_error("Could not build client config".into()))?;
-
-                    let data = bytes::Bytes::from(profile_text);
-
-                    bot.send_document(user_id, InputFile::memory(data))
-                        .send()
-                        .await?;
+                    send_profile_config(&bot, &profile, &cfg, server, &server_key.public_key, action, lang)
+                        .await
+                        .map_err(process_error(tr(lang, "error_export")))?;
                 }
-                ManageProfileAction::GetQR => {
-                    let profile = storage
-                        .get_user_profile(user_id.into(), &name)
+            },
+            UserCallbackQuery::NewConfig { name, action, pending } => {
+                let profile = storage
+                    .get_user_profile(user_id, &name)
+                    .await
+                    .map_err(process_error(tr(lang, "error_get_profile")))?;
+                let server = &fleet.node(profile.server_id)?.server;
+                let pending_key = if pending {
+                    storage
+                        .get_pending_server_key(server.id)
                         .await
-                        .map_err(process_error("Could not get user profile".into()))?;
+                        .map_err(process_error(tr(lang, "error_server_key")))?
+                } else {
+                    None
+                };
+                // After the switch the new key is the active one
+                let server_key = match pending_key {
+                    Some(key) => key,
+                    None => storage
+                        .get_active_server_key(server.id)
+                        .await
+                        .map_err(process_error(tr(lang, "error_server_key")))?,
+                };
 
-                    let peer_cfg = PeerConfig::new(&profile, &cfg)
-                        .map_err(process_error("Could not build peer config".into()))?;
-                    let profile_text = build_peer_config(&peer_cfg)
-                        .map_err(|e| anyhow!(e))
-                        .map_err(process_error("Could not build client config".into()))?;
-
-                    let path = get_qr_path();
-
-                    let cmd = Command::new("qrencode")
-                        .arg("-o")
-                        .arg(&path)
-                        .stdin(Stdio::piped())
-                        .stdout(Stdio::piped())
-                        .spawn()
-                        .map_err(|e| {
-                            process_error("Could not generate QR code".into())(anyhow!(e))
-                        })?;
-
-                    cmd.stdin
-                        .as_ref()
-                        .unwrap()
-                        .write_all(profile_text.as_bytes())
-                        .map_err(|e| {
-                            process_error("Could not generate QR code".into())(anyhow!(e))
-                        })?;
-
-                    let _ = cmd.wait_with_output()?;
-
-                    bot.send_photo(user_id, InputFile::file(&path))
+                send_profile_config(&bot, &profile, &cfg, server, &server_key.public_key, action, lang)
+                    .await
+                    .map_err(process_error(tr(lang, "error_export")))?;
+            }
+            UserCallbackQuery::ChooseMoveTarget { name } => {
+                let profile = storage
+                    .get_user_profile(user_id, &name)
+                    .await
+                    .map_err(process_error(tr(lang, "error_get_profile")))?;
+                let keyboard: Vec<Vec<InlineKeyboardButton>> = fleet
+                    .enabled()
+                    .filter(|node| node.server.id != profile.server_id)
+                    .map(|node| {
+                        vec![InlineKeyboardButton::callback(
+                            server_label(&node.server, &cfg),
+                            serde_json::to_string(&UserCallbackQuery::MoveProfile {
+                                name: name.clone(),
+                                server_id: node.server.id,
+                            })
+                            .unwrap(),
+                        )]
+                    })
+                    .collect();
+                if keyboard.is_empty() {
+                    bot.send_message(user_id, tr(lang, "no_other_location"))
                         .send()
                         .await?;
+                    return Ok(());
                 }
-            },
+                bot.edit_message_text(
+                    user_id,
+                    cq.message.unwrap().id,
+                    trf(lang, "move_profile_to", &[("name", &name)]),
+                )
+                .reply_markup(InlineKeyboardMarkup::new(keyboard))
+                .send()
+                .await?;
+            }
+            UserCallbackQuery::MoveProfile { name, server_id } => {
+                let profile = storage
+                    .get_user_profile(user_id, &name)
+                    .await
+                    .map_err(process_error(tr(lang, "error_get_profile")))?;
+                // Users keep their keypair, only the address and the server change
+                let moved = fleet
+                    .move_profile(&storage, &cfg, &profile, server_id, true)
+                    .await
+                    .map_err(process_error(tr(lang, "error_move")))?;
+                let server = &fleet.node(moved.server_id)?.server;
+                notify_profile_moved(&bot, &storage, &cfg, &moved, server).await?;
+            }
+            UserCallbackQuery::ProfileUsage { name } => {
+                let profile = storage
+                    .get_user_profile(user_id, &name)
+                    .await
+                    .map_err(process_error(tr(lang, "error_get_profile")))?;
+                let mut text = render_usage(&storage, &cfg, &fleet, std::slice::from_ref(&profile), lang)
+                    .await
+                    .map_err(process_error(tr(lang, "error_usage")))?;
+                let sessions = storage
+                    .get_profile_sessions(&profile, 5)
+                    .await
+                    .map_err(process_error(tr(lang, "error_sessions")))?;
+                if !sessions.is_empty() {
+                    text.push_str(&format!("\n\n{}", tr(lang, "recent_sessions")));
+                    for session in sessions {
+                        text.push_str(&format!("\n{}", render_session(&session, lang)));
+                    }
+                }
+                let keyboard = chart_keyboard(lang, |range| {
+                    serde_json::to_string(&UserCallbackQuery::UsageChart {
+                        name: Some(name.clone()),
+                        range,
+                    })
+                    .unwrap()
+                });
+                bot.send_message(user_id, text)
+                    .reply_markup(keyboard)
+                    .send()
+                    .await?;
+            }
+            UserCallbackQuery::UsageChart { name, range } => {
+                let subject = match &name {
+                    Some(name) => {
+                        storage
+                            .get_user_profile(user_id, name)
+                            .await
+                            .map_err(process_error(tr(lang, "error_get_profile")))?;
+                        trf(lang, "chart_subject_profile", &[("name", name)])
+                    }
+                    None => tr(lang, "chart_subject_own"),
+                };
+                send_traffic_chart(&bot, chat_id, &storage, Some(user_id), name.as_deref(), &subject, range)
+                    .await
+                    .map_err(process_error(tr(lang, "error_chart")))?;
+            }
             UserCallbackQuery::RequestAccess => {
                 let user_status = storage.get_user_status(user_id).await?;
                 match user_status {
                     UserStatus::Restricted => {
-                        bot.send_message(chat_id, "Go away").send().await?;
+                        bot.send_message(chat_id, tr(lang, "go_away")).send().await?;
                         return Ok(());
                     }
                     UserStatus::Requested => {
-                        bot.send_message(chat_id, "You are already requested for access")
+                        bot.send_message(chat_id, tr(lang, "already_requested"))
                             .send()
                             .await?;
                         return Ok(());
                     }
                     UserStatus::Granted => {
-                        bot.send_message(chat_id, "You are already has access")
+                        bot.send_message(chat_id, tr(lang, "already_granted"))
                             .send()
                             .await?;
                         return Ok(());
@@ -388,19 +623,271 @@ pub async fn on_callback_query(
                         storage
                             .update_user_status(user_id, UserStatus::Requested)
                             .await?;
-                        bot.send_message(chat_id, "Request sent").send().await?;
-                        bot.send_message(ChatId(cfg.admin_id), "New access request were recieved")
+                        bot.send_message(chat_id, tr(lang, "request_sent")).send().await?;
+                        let admin = storage.get_user(UserId(cfg.admin_id as u64)).await?;
+                        bot.send_message(ChatId(cfg.admin_id), tr(Lang::of(&admin), "new_access_request"))
                             .send()
                             .await?;
                         return Ok(());
                     }
                 }
             }
+            UserCallbackQuery::SetLanguage { lang } => {
+                storage
+                    .set_user_language(user_id, lang.code())
+                    .await
+                    .map_err(process_error(tr(lang, "error_language")))?;
+                bot.edit_message_text(user_id, cq.message.unwrap().id, tr(lang, "language_changed"))
+                    .send()
+                    .await?;
+                if chat_id == ChatId(cfg.admin_id) {
+                    if let Err(e) = register_admin_commands(&bot, &cfg, lang).await {
+                        tracing::warn!("Could not update admin commands: {}", e);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends the config of the profile to its owner.
+pub async fn send_profile_config(
+    bot: &Bot,
+    profile: &Profile,
+    cfg: &CfgPtr,
+    server: &VpnServer,
+    server_public_key: &str,
+    action: ManageProfileAction,
+    lang: Lang,
+) -> Result<()> {
+    let peer_cfg = PeerConfig::new(profile, cfg, server, server_public_key)?;
+    let profile_text = build_peer_config(&peer_cfg).map_err(|e| anyhow!(e))?;
+    let user_id = profile.user_id;
+
+    match action {
+        ManageProfileAction::Delete => {
+            return Err(anyhow!("Delete is not an export action"));
+        }
+        ManageProfileAction::GetText => {
+            bot.send_message(user_id, format!("{}\n\n```\n{}\n```", tr(lang, "config_text"), profile_text))
+                .parse_mode(ParseMode::MarkdownV2)
+                .send()
+                .await?;
+        }
+        ManageProfileAction::GetFile => {
+            let data = bytes::Bytes::from(profile_text);
+
+            bot.send_document(user_id, InputFile::memory(data))
+                .send()
+                .await?;
+        }
+        ManageProfileAction::GetQR => {
+            let path = get_qr_path();
+
+            let cmd = Command::new("qrencode")
+                .arg("-o")
+                .arg(&path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()?;
+
+            cmd.stdin
+                .as_ref()
+                .unwrap()
+                .write_all(profile_text.as_bytes())?;
+
+            let _ = cmd.wait_with_output()?;
+
+            bot.send_photo(user_id, InputFile::file(&path))
+                .send()
+                .await?;
         }
     }
     Ok(())
 }
 
+/// Export buttons of a profile's new config. With `pending` the config is
+/// built with the server key that is about to be activated, if any.
+pub fn new_config_keyboard(name: &str, pending: bool, lang: Lang) -> InlineKeyboardMarkup {
+    let button = |key: &str, action: ManageProfileAction| {
+        InlineKeyboardButton::callback(
+            tr(lang, key),
+            serde_json::to_string(&UserCallbackQuery::NewConfig {
+                name: name.to_owned(),
+                action,
+                pending,
+            })
+            .unwrap(),
+        )
+    };
+    InlineKeyboardMarkup::new(vec![vec![
+        button("button_as_text", ManageProfileAction::GetText),
+        button("button_as_file", ManageProfileAction::GetFile),
+        button("button_as_qr", ManageProfileAction::GetQR),
+    ]])
+}
+
+/// Asks the owner of a moved profile to import its new config.
+pub async fn notify_profile_moved(
+    bot: &Bot,
+    storage: &StoragePtr,
+    cfg: &CfgPtr,
+    profile: &Profile,
+    server: &VpnServer,
+) -> Result<()> {
+    let lang = Lang::of(&storage.get_user(profile.user_id).await?);
+    let text = trf(
+        lang,
+        "profile_moved",
+        &[("name", &profile.name), ("location", &server_label(server, cfg))],
+    );
+    bot.send_message(profile.user_id, text)
+        .reply_markup(new_config_keyboard(&profile.name, false, lang))
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Traffic history and presence of every profile, from what the statistics
+/// collector has recorded.
+async fn render_usage(
+    storage: &StoragePtr,
+    cfg: &CfgPtr,
+    fleet: &FleetPtr,
+    profiles: &[Profile],
+    lang: Lang,
+) -> Result<String> {
+    let now = Utc::now();
+    let traffic = |traffic: Traffic| {
+        trf(lang, "traffic", &[("rx", &format_bytes(traffic.rx)), ("tx", &format_bytes(traffic.tx))])
+    };
+    let mut blocks = vec![];
+    for profile in profiles {
+        let usage = storage.get_profile_usage(profile).await?;
+        let location = match fleet.node(profile.server_id) {
+            Ok(node) => server_label(&node.server, cfg),
+            Err(_) => tr(lang, "unknown_location"),
+        };
+        let handshake = match usage.latest_handshake {
+            Some(handshake) => {
+                let age = (now - handshake).to_std().unwrap_or_default();
+                let age = std::time::Duration::from_secs(age.as_secs());
+                trf(lang, "ago", &[("duration", &humantime::format_duration(age))])
+            }
+            None => tr(lang, "never"),
+        };
+        let endpoint = match usage.last_endpoint.as_deref() {
+            Some(endpoint) => endpoint_host(endpoint).to_owned(),
+            None => tr(lang, "unknown"),
+        };
+        blocks.push(trf(
+            lang,
+            "profile_usage",
+            &[
+                ("name", &profile.name),
+                ("location", &location),
+                ("state", &tr(lang, if usage.is_online(now) { "online" } else { "offline" })),
+                ("handshake", &handshake),
+                ("endpoint", &endpoint),
+                ("today", &traffic(usage.today)),
+                ("month", &traffic(usage.month)),
+                ("total", &traffic(usage.total)),
+            ],
+        ));
+    }
+    Ok(blocks.join("\n\n"))
+}
+
+/// Start, duration and traffic of a session, e.g.
+/// "2023-04-10 12:30 UTC, 1h 5m, 10 MB rx, 2 MB tx".
+pub fn render_session(session: &Session, lang: Lang) -> String {
+    let end = session.ended_at.unwrap_or_else(Utc::now);
+    let duration = (end - session.started_at).to_std().unwrap_or_default();
+    let duration = std::time::Duration::from_secs(duration.as_secs() / 60 * 60);
+    let mut duration = if duration.is_zero() {
+        tr(lang, "under_a_minute")
+    } else {
+        humantime::format_duration(duration).to_string()
+    };
+    if session.ended_at.is_none() {
+        duration = trf(lang, "so_far", &[("duration", &duration)]);
+    }
+    format!(
+        "{}, {}, {}",
+        session.started_at.format("%Y-%m-%d %H:%M UTC"),
+        duration,
+        trf(lang, "traffic", &[("rx", &format_bytes(session.rx)), ("tx", &format_bytes(session.tx))])
+    )
+}
+
+/// Range buttons of a traffic chart, `callback` builds the data of each.
+pub fn chart_keyboard(lang: Lang, callback: impl Fn(ChartRange) -> String) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![ChartRange::ALL
+        .iter()
+        .map(|range| {
+            let range_name = tr(lang, &format!("chart_range_{}", range.label()));
+            let label = trf(lang, "button_chart", &[("range", &range_name)]);
+            InlineKeyboardButton::callback(label, callback(*range))
+        })
+        .collect::<Vec<_>>()])
+}
+
+/// Sends a chart of the traffic of a profile, of all profiles of a user or,
+/// without a user, of everyone. The caption is in the language of the
+/// recipient.
+pub async fn send_traffic_chart(
+    bot: &Bot,
+    chat_id: ChatId,
+    storage: &StoragePtr,
+    user_id: Option<UserId>,
+    profile: Option<&str>,
+    subject: &str,
+    range: ChartRange,
+) -> Result<()> {
+    let now = Utc::now();
+    let samples = storage
+        .get_traffic_history(user_id, profile, range.start(now))
+        .await?;
+    let buckets = fill_buckets(range, now, &samples);
+    let rx: u64 = buckets.iter().map(|t| t.rx).sum();
+    let tx: u64 = buckets.iter().map(|t| t.tx).sum();
+    let png = Chart { buckets }.to_png()?;
+    let lang = Lang::of(&storage.get_user(UserId(chat_id.0 as u64)).await?);
+    let caption = trf(
+        lang,
+        "chart_caption",
+        &[
+            ("subject", &subject),
+            ("period", &tr(lang, &format!("chart_period_{}", range.label()))),
+            ("rx", &format_bytes(rx)),
+            ("tx", &format_bytes(tx)),
+        ],
+    );
+    bot.send_photo(chat_id, InputFile::memory(png).file_name("traffic.png"))
+        .caption(caption)
+        .send()
+        .await?;
+    Ok(())
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    byte_unit::Byte::from_bytes(bytes).get_appropriate_unit(true).to_string()
+}
+
+/// Button text of a server, e.g. "Netherlands · Full tunnel".
+fn server_label(server: &VpnServer, cfg: &CfgPtr) -> String {
+    let mut parts = vec![if server.region.is_empty() { server.name.clone() } else { server.region.clone() }];
+    if cfg.interfaces.len() > 1 {
+        if let Ok(interface) = cfg.interface(&server.interface) {
+            if !interface.description.is_empty() {
+                parts.push(interface.description.clone());
+            }
+        }
+    }
+    parts.join(" · ")
+}
+
 fn get_qr_path() -> String {
     let mut rng = rand::thread_rng();
     let num: u64 = rng.gen();
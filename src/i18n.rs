@@ -0,0 +1,141 @@
+use std::{collections::HashMap, fmt};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::User;
+
+/// Language of the bot's texts. Short names keep the callback data within
+/// Telegram's 64 bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Lang {
+    #[default]
+    #[serde(rename = "en")]
+    En,
+    #[serde(rename = "ru")]
+    Ru,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 2] = [Lang::En, Lang::Ru];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Ru => "ru",
+        }
+    }
+
+    /// Name of the language in itself, for the language picker.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lang::En => "English",
+            Lang::Ru => "Русский",
+        }
+    }
+
+    /// Language of an IETF tag such as "ru" or "en-US", English for unknown ones.
+    pub fn from_code(code: Option<&str>) -> Lang {
+        let primary = code.unwrap_or_default().split('-').next().unwrap_or_default();
+        Lang::ALL
+            .into_iter()
+            .find(|lang| lang.code().eq_ignore_ascii_case(primary))
+            .unwrap_or_default()
+    }
+
+    /// Language the user chose with /language, otherwise the one of their
+    /// Telegram client.
+    pub fn of(user: &User) -> Lang {
+        Lang::from_code(user.language.as_deref().or(user.language_code.as_deref()))
+    }
+
+    fn source(self) -> &'static str {
+        match self {
+            Lang::En => include_str!("../locales/en.yaml"),
+            Lang::Ru => include_str!("../locales/ru.yaml"),
+        }
+    }
+}
+
+static CATALOGUE: Lazy<HashMap<Lang, HashMap<String, String>>> = Lazy::new(|| {
+    Lang::ALL
+        .into_iter()
+        .map(|lang| {
+            let messages = serde_yaml::from_str(lang.source())
+                .unwrap_or_else(|e| panic!("Invalid locale {}: {}", lang.code(), e));
+            (lang, messages)
+        })
+        .collect()
+});
+
+/// Message `key` in `lang`, falling back to English and then to the key
+/// itself, so that a missing text never breaks a handler.
+pub fn tr(lang: Lang, key: &str) -> String {
+    trf(lang, key, &[])
+}
+
+/// Like `tr`, replacing every `{name}` of the message with its argument.
+pub fn trf(lang: Lang, key: &str, args: &[(&str, &(dyn fmt::Display + Sync))]) -> String {
+    let message = CATALOGUE[&lang]
+        .get(key)
+        .or_else(|| CATALOGUE[&Lang::En].get(key));
+    let mut text = match message {
+        Some(message) => message.clone(),
+        None => {
+            tracing::warn!("Missing message {} of locale {}", key, lang.code());
+            return key.to_owned();
+        }
+    };
+    for (name, value) in args {
+        text = text.replace(&format!("{{{}}}", name), &value.to_string());
+    }
+    text
+}
+
+#[cfg(test)]
+fn placeholders(message: &str) -> std::collections::BTreeSet<&str> {
+    message
+        .split('{')
+        .skip(1)
+        .filter_map(|part| part.split_once('}').map(|(name, _)| name))
+        .collect()
+}
+
+#[test]
+fn test_locales_are_complete() {
+    let english = &CATALOGUE[&Lang::En];
+    for lang in Lang::ALL {
+        let messages = &CATALOGUE[&lang];
+        for (key, message) in english {
+            let translation = messages
+                .get(key)
+                .unwrap_or_else(|| panic!("Locale {} has no message {}", lang.code(), key));
+            assert_eq!(
+                placeholders(translation),
+                placeholders(message),
+                "Placeholders of {} in locale {}",
+                key,
+                lang.code()
+            );
+        }
+        for key in messages.keys() {
+            assert!(english.contains_key(key), "Locale {} has unknown message {}", lang.code(), key);
+        }
+    }
+}
+
+#[test]
+fn test_tr() {
+    assert_eq!(tr(Lang::En, "access_denied"), "Access denied");
+    assert_eq!(trf(Lang::En, "your_id", &[("id", &42)]), "Your id: 42");
+    assert_eq!(tr(Lang::Ru, "no_such_message"), "no_such_message");
+}
+
+#[test]
+fn test_from_code() {
+    assert_eq!(Lang::from_code(Some("ru")), Lang::Ru);
+    assert_eq!(Lang::from_code(Some("RU-ru")), Lang::Ru);
+    assert_eq!(Lang::from_code(Some("en-GB")), Lang::En);
+    assert_eq!(Lang::from_code(Some("de")), Lang::En);
+    assert_eq!(Lang::from_code(None), Lang::En);
+}
@@ -0,0 +1,189 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Result;
+use clokwerk::{AsyncScheduler, TimeUnits};
+use chrono::{DateTime, Utc};
+use teloxide::prelude::*;
+
+use crate::{
+    cfg::CfgPtr,
+    fleet::{FleetPtr, Node},
+    handlers::new_config_keyboard,
+    i18n::{trf, Lang},
+    storage::StoragePtr,
+};
+
+/// Generates a pending key for every server and sends every user a config for
+/// each of their profiles built with it. Keys are switched by `run_watcher`
+/// once the overlap period is over.
+pub async fn start_rotation(
+    bot: &Bot,
+    storage: &StoragePtr,
+    cfg: &CfgPtr,
+    fleet: &FleetPtr,
+) -> Result<DateTime<Utc>> {
+    let overlap = humantime::parse_duration(&cfg.server_key_rotation_overlap)?;
+    let activate_at = Utc::now() + chrono::Duration::from_std(overlap)?;
+    for node in fleet.nodes() {
+        if storage.get_pending_server_key(node.server.id).await?.is_some() {
+            return Err(anyhow::anyhow!("Key rotation of {} is already in progress", node.server.name));
+        }
+    }
+    for node in fleet.nodes() {
+        storage.add_pending_server_key(node.server.id, activate_at).await?;
+    }
+
+    let langs: HashMap<UserId, Lang> = storage
+        .get_users()
+        .await?
+        .iter()
+        .map(|user| (user.user_id, Lang::of(user)))
+        .collect();
+    for profile in storage.get_profiles().await? {
+        let lang = langs.get(&profile.user_id).copied().unwrap_or_default();
+        let text = trf(
+            lang,
+            "server_key_rotation",
+            &[("time", &activate_at.format("%Y-%m-%d %H:%M")), ("name", &profile.name)],
+        );
+        let res = bot
+            .send_message(profile.user_id, text)
+            .reply_markup(new_config_keyboard(&profile.name, true, lang))
+            .send()
+            .await;
+        if let Err(e) = res {
+            tracing::warn!("Could not notify user {} about key rotation: {}", profile.user_id, e);
+        }
+    }
+
+    Ok(activate_at)
+}
+
+/// Lists profiles of every server which did and did not handshake since the
+/// active key was switched on.
+pub async fn build_report(storage: &StoragePtr, fleet: &FleetPtr) -> Result<String> {
+    let mut text = String::new();
+    for node in fleet.nodes() {
+        text.push_str(&build_node_report(storage, node).await?);
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+async fn build_node_report(storage: &StoragePtr, node: &Node) -> Result<String> {
+    let server = &node.server;
+    let server_key = storage.get_active_server_key(server.id).await?;
+    let activated_at = server_key.activated_at.unwrap_or(server_key.created_at).timestamp() as u64;
+    let handshakes: HashMap<String, u64> = node
+        .client
+        .get_statistics(&server.interface)
+        .await?
+        .into_iter()
+        .map(|e| (e.pubkey, e.latest_handshake))
+        .collect();
+
+    let mut updated = vec![];
+    let mut outdated = vec![];
+    for profile in storage.get_server_profiles(server.id).await? {
+        let line = format!("{} (user {})", profile.name, profile.user_id);
+        match handshakes.get(&profile.public_key) {
+            Some(handshake) if *handshake >= activated_at => updated.push(line),
+            _ => outdated.push(line),
+        }
+    }
+
+    let mut text = format!(
+        "{}: key {} active since {}\n",
+        server.name,
+        server_key.public_key,
+        server_key
+            .activated_at
+            .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or("(unknown)".into())
+    );
+    if let Some(pending) = storage.get_pending_server_key(server.id).await? {
+        text.push_str(&format!(
+            "Pending key {} will be activated at {}\n",
+            pending.public_key,
+            pending
+                .activate_at
+                .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or("(unknown)".into())
+        ));
+    }
+    text.push_str(&format!("\nConnected with the new key ({}):\n", updated.len()));
+    updated.iter().for_each(|line| text.push_str(&format!("{}\n", line)));
+    text.push_str(&format!("\nNot connected yet ({}):\n", outdated.len()));
+    outdated.iter().for_each(|line| text.push_str(&format!("{}\n", line)));
+    Ok(text)
+}
+
+async fn check_rotation(bot: &Bot, storage: &StoragePtr, cfg: &CfgPtr, fleet: &FleetPtr) -> Result<()> {
+    for node in fleet.nodes() {
+        if let Err(e) = check_node_rotation(bot, storage, cfg, node).await {
+            tracing::error!("Server key rotation check of {} failed: {}", node.server.name, e);
+        }
+    }
+    Ok(())
+}
+
+async fn check_node_rotation(bot: &Bot, storage: &StoragePtr, cfg: &CfgPtr, node: &Node) -> Result<()> {
+    let admin_id = ChatId(cfg.admin_id);
+    let server = &node.server;
+
+    if let Some(pending) = storage.get_pending_server_key(server.id).await? {
+        if pending.activate_at.is_none_or(|t| t <= Utc::now()) {
+            // The config is built from the active key, so it is switched
+            // first and switched back if the server does not take it
+            let previous = storage.get_active_server_key(server.id).await?;
+            storage.activate_server_key(server.id, pending.id).await?;
+            if let Err(e) = node.client.sync_config(storage, cfg, server).await {
+                storage.revert_server_key_activation(server.id, pending.id, previous.id).await?;
+                return Err(e.into());
+            }
+            tracing::info!("Server key of {} switched to {}", server.name, pending.public_key);
+            let text = format!("Server key of {} switched to {}", server.name, pending.public_key);
+            bot.send_message(admin_id, text).send().await?;
+        }
+    }
+
+    let active = storage.get_active_server_key(server.id).await?;
+    // Keys seeded from config were never rotated, so there is nothing to report
+    if active.activate_at.is_none() || active.reported_at.is_some() {
+        return Ok(());
+    }
+    let report_delay = humantime::parse_duration(&cfg.server_key_rotation_report_delay)?;
+    let report_at = active.activated_at.unwrap_or(active.created_at) + chrono::Duration::from_std(report_delay)?;
+    if report_at <= Utc::now() {
+        let report = build_node_report(storage, node).await?;
+        bot.send_message(admin_id, report).send().await?;
+        storage.mark_server_key_reported(active.id).await?;
+    }
+    Ok(())
+}
+
+pub fn run_watcher(
+    bot: Bot,
+    storage: StoragePtr,
+    cfg: CfgPtr,
+    fleet: FleetPtr,
+) -> tokio::task::JoinHandle<()> {
+    let mut scheduler = AsyncScheduler::new();
+    scheduler.every(1.minute()).run(move || {
+        let bot = bot.clone();
+        let storage = storage.clone();
+        let cfg = cfg.clone();
+        let fleet = fleet.clone();
+        async move {
+            if let Err(e) = check_rotation(&bot, &storage, &cfg, &fleet).await {
+                tracing::error!("Server key rotation check failed: {}", e);
+            }
+        }
+    });
+    tokio::spawn(async move {
+        loop {
+            scheduler.run_pending().await;
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        }
+    })
+}
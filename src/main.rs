@@ -1,48 +1,154 @@
+mod bot_metrics;
+mod broadcast;
 mod cfg;
+mod charts;
 mod control_client;
+mod crypto;
+mod fleet;
 mod handlers;
+mod i18n;
+mod key_rotation;
+mod metrics;
+mod presence;
+mod reconciler;
 mod rpc;
 mod statistics;
 mod storage;
+mod telemetry;
 mod wireguard;
 mod statistics_collector;
 
 use anyhow::Result;
+use clap::{Parser, Subcommand};
 use std::sync::Arc;
 use teloxide::{dispatching::dialogue::InMemStorage, prelude::*};
-use tracing::level_filters::LevelFilter;
-use tracing_subscriber::{prelude::*, registry::Registry};
 
-fn main() -> Result<()> {
-    let fmt_layer = tracing_subscriber::fmt::layer()
-        .with_target(false)
-        .with_filter(LevelFilter::DEBUG);
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the bot (default)
+    Run,
+    /// Print a new random master key
+    GenerateMasterKey,
+    /// Re-wrap all profile keys with the current master key
+    RotateMasterKey,
+    /// Encrypt profile keys stored before encryption at rest
+    EncryptPrivateKeys,
+}
 
-    Registry::default().with(fmt_layer).try_init().unwrap();
+fn main() -> Result<()> {
+    let args = Args::parse();
 
     let rt = tokio::runtime::Runtime::new()?;
     let result: Result<()> = rt.block_on(async move {
+        let command = args.command.unwrap_or(Command::Run);
+        if let Command::GenerateMasterKey = command {
+            println!("{}", crypto::MasterKey::generate());
+            return Ok(());
+        }
+
+        telemetry::init(&cfg::get_log_config()?, "telegram_bot")?;
         let service_config = Arc::new(cfg::get_config()?);
-        let storage = Arc::new(storage::Storage::new().await?);
+
+        match command {
+            Command::Run | Command::GenerateMasterKey => {}
+            Command::RotateMasterKey => {
+                let storage = storage::Storage::new(&service_config).await?;
+                let rotated = storage.rotate_master_key().await?;
+                tracing::info!("Master key rotated for {} profiles", rotated);
+                return Ok(());
+            }
+            Command::EncryptPrivateKeys => {
+                let storage = storage::Storage::new(&service_config).await?;
+                let encrypted = storage.encrypt_plaintext_private_keys().await?;
+                tracing::info!("Encrypted private keys of {} profiles", encrypted);
+                return Ok(());
+            }
+        }
+
+        let storage = Arc::new(storage::Storage::new(&service_config).await?);
         let bot = Bot::new(service_config.bot_token.clone());
 
-        control_client::start_wireguard_server(&service_config).await?;
-        control_client::sync_config(&storage, &service_config).await?;
+        let bot_metrics = Arc::new(bot_metrics::BotMetrics::new()?);
+        if let Some(address) = &service_config.metrics_address {
+            metrics::serve(address, bot_metrics.registry.clone())?;
+        }
+        let fleet = Arc::new(fleet::Fleet::new(&storage, &service_config, &bot_metrics).await?);
+
+        // One server being down must not keep the bot from serving the others
+        for node in fleet.nodes() {
+            let server = &node.server;
+            let result: Result<(), control_client::ControlError> = async {
+                if node.client.is_serving(&server.interface).await? {
+                    tracing::info!("WireGuard interface {} of {} is already up", server.interface, server.name);
+                } else {
+                    node.client.start_wireguard_server(&storage, &service_config, server).await?;
+                }
+                node.client.sync_config(&storage, &service_config, server).await
+            }
+            .await;
+            if let Err(e) = result {
+                tracing::error!("Could not bring up server {}: {}", server.name, e);
+            }
+        }
+
+        statistics_collector::run_collector(
+            bot.clone(),
+            storage.clone(),
+            service_config.clone(),
+            fleet.clone(),
+            bot_metrics.clone(),
+        )?;
+        key_rotation::run_watcher(
+            bot.clone(),
+            storage.clone(),
+            service_config.clone(),
+            fleet.clone(),
+        );
+        reconciler::run_reconciler(
+            bot.clone(),
+            storage.clone(),
+            service_config.clone(),
+            fleet.clone(),
+            bot_metrics.clone(),
+        )?;
+
+        // The menus are only hints, the bot works without them
+        if let Err(e) = handlers::register_commands(&bot, &storage, &service_config).await {
+            tracing::warn!("Could not register bot commands: {}", e);
+        }
 
-        statistics_collector::run_collector();
+        let broadcast_queue = broadcast::BroadcastQueue::start(bot.clone(), storage.clone());
 
         Dispatcher::builder(bot, handlers::get_handler(service_config.clone()))
             .dependencies(dptree::deps![
                 service_config.clone(),
                 storage.clone(),
-                InMemStorage::<handlers::AddProfileDialogueState>::new()
+                fleet.clone(),
+                broadcast_queue,
+                InMemStorage::<handlers::AddProfileDialogueState>::new(),
+                InMemStorage::<handlers::BroadcastDialogueState>::new()
             ])
+            .error_handler({
+                let bot_metrics = bot_metrics.clone();
+                Arc::new(move |e: anyhow::Error| {
+                    bot_metrics.handler_errors.inc();
+                    tracing::error!("Error in handler: {}", e);
+                    async {}
+                })
+            })
             .enable_ctrlc_handler()
             .build()
             .dispatch()
             .await;
         Ok(())
     });
+    telemetry::shutdown();
 
     if let Err(e) = result {
         eprintln!("Program finished with error: {}", e);
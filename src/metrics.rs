@@ -0,0 +1,51 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use anyhow::Result;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode,
+};
+use prometheus::{Encoder, Registry, TextEncoder};
+
+/// Serves `registry` in the Prometheus text format at `/metrics`.
+pub fn serve(address: &str, registry: Registry) -> Result<tokio::task::JoinHandle<()>> {
+    let address: SocketAddr = address.parse()?;
+    let make_service = make_service_fn(move |_| {
+        let registry = registry.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let registry = registry.clone();
+                async move { Ok::<_, Infallible>(handle(&registry, request)) }
+            }))
+        }
+    });
+    let server = hyper::Server::try_bind(&address)?.serve(make_service);
+    tracing::info!("Serving metrics on http://{}/metrics", address);
+    Ok(tokio::spawn(async move {
+        if let Err(e) = server.await {
+            tracing::error!("Metrics server failed: {}", e);
+        }
+    }))
+}
+
+fn handle(registry: &Registry, request: Request<Body>) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        let mut response = Response::new(Body::from("Not found"));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    if let Err(e) = encoder.encode(&registry.gather(), &mut buffer) {
+        let mut response = Response::new(Body::from(format!("Could not encode metrics: {}", e)));
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        return response;
+    }
+    let mut response = Response::new(Body::from(buffer));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, encoder.format_type().parse().unwrap());
+    response
+}
@@ -0,0 +1,201 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use teloxide::prelude::*;
+
+use crate::{
+    cfg::CfgPtr,
+    handlers::format_bytes,
+    i18n::{trf, Lang},
+    statistics::{endpoint_host, ClientEntry, Traffic, ONLINE_HANDSHAKE_AGE},
+    storage::{Profile, Session, StoragePtr, VpnServer},
+};
+
+#[derive(Debug, PartialEq)]
+pub enum Transition {
+    Connected,
+    Active,
+    Disconnected,
+    Idle,
+}
+
+/// What happened to a peer since the previous sample. It is online while its
+/// latest handshake is recent enough.
+pub fn transition(has_session: bool, latest_handshake: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Transition {
+    let online = latest_handshake
+        .map(|handshake| now - handshake < Duration::seconds(ONLINE_HANDSHAKE_AGE as i64))
+        .unwrap_or(false);
+    match (has_session, online) {
+        (false, true) => Transition::Connected,
+        (true, true) => Transition::Active,
+        (true, false) => Transition::Disconnected,
+        (false, false) => Transition::Idle,
+    }
+}
+
+/// Opens, extends and closes sessions of the profiles of a reachable server.
+/// `traffic` is what every peer transferred since the previous sample.
+pub async fn update(
+    bot: &Bot,
+    storage: &StoragePtr,
+    cfg: &CfgPtr,
+    server: &VpnServer,
+    profiles: &HashMap<String, Profile>,
+    entries: &[ClientEntry],
+    traffic: &HashMap<String, Traffic>,
+) -> Result<()> {
+    let now = Utc::now();
+    let mut open: HashMap<(UserId, String), Session> = storage
+        .get_open_sessions(Some(server.id))
+        .await?
+        .into_iter()
+        .map(|session| ((session.user_id, session.profile.clone()), session))
+        .collect();
+
+    for entry in entries {
+        let profile = match profiles.get(&entry.pubkey) {
+            Some(profile) => profile,
+            None => continue,
+        };
+        let session = open.remove(&(profile.user_id, profile.name.clone()));
+        let handshake = entry.handshake_time();
+        let delta = traffic.get(&entry.pubkey).copied().unwrap_or_default();
+        match (transition(session.is_some(), handshake, now), session) {
+            (Transition::Connected, _) => {
+                let mut session = storage
+                    .open_session(profile, handshake.unwrap_or(now), delta, None)
+                    .await?;
+                record_endpoint(bot, storage, cfg, profile, &mut session, entry, now).await?;
+                let text = |lang| match &session.endpoint {
+                    Some(endpoint) => trf(
+                        lang,
+                        "session_connected_from",
+                        &[("name", &profile.name), ("server", &server.name), ("endpoint", &endpoint_host(endpoint))],
+                    ),
+                    None => trf(lang, "session_connected", &[("name", &profile.name), ("server", &server.name)]),
+                };
+                notify(bot, storage, &session, text).await;
+            }
+            (Transition::Active, Some(session)) => {
+                let mut session = storage.update_session(session.id, handshake, delta, false).await?;
+                record_endpoint(bot, storage, cfg, profile, &mut session, entry, now).await?;
+            }
+            (Transition::Disconnected, Some(session)) => {
+                let session = storage.update_session(session.id, handshake, delta, true).await?;
+                notify(bot, storage, &session, |lang| disconnected_text(&session, lang)).await;
+            }
+            _ => {}
+        }
+    }
+
+    // Peers which are gone from the server, e.g. of deleted or moved profiles
+    for session in open.into_values() {
+        storage
+            .update_session(session.id, None, Traffic::default(), true)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Stores a new endpoint of the session and alerts admin if the profile
+/// looks shared, see `shared_config_endpoints`.
+async fn record_endpoint(
+    bot: &Bot,
+    storage: &StoragePtr,
+    cfg: &CfgPtr,
+    profile: &Profile,
+    session: &mut Session,
+    entry: &ClientEntry,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let endpoint = match &entry.endpoint {
+        Some(endpoint) if session.endpoint.as_ref() != Some(endpoint) => endpoint,
+        _ => return Ok(()),
+    };
+    let threshold = match cfg.shared_config_endpoints {
+        Some(threshold) => threshold,
+        None => {
+            storage.record_session_endpoint(session, endpoint).await?;
+            return Ok(());
+        }
+    };
+    let window = Duration::from_std(humantime::parse_duration(&cfg.shared_config_window)?)?;
+    let previous = storage
+        .get_recent_endpoints(profile.user_id, &profile.name, now - window)
+        .await?;
+    storage.record_session_endpoint(session, endpoint).await?;
+
+    let previous: Vec<&str> = previous.iter().map(|endpoint| endpoint_host(endpoint)).collect();
+    if reaches_threshold(&previous, endpoint_host(endpoint), threshold) {
+        let text = format!(
+            "Profile {} of user {} connected from {} addresses within {}, its config may be shared",
+            profile.name, profile.user_id, threshold, cfg.shared_config_window
+        );
+        if let Err(e) = bot.send_message(ChatId(cfg.admin_id), text).send().await {
+            tracing::warn!("Could not alert admin about profile {}: {}", profile.name, e);
+        }
+    }
+    Ok(())
+}
+
+/// Whether a new address makes `threshold` distinct ones. Only reaching it is
+/// reported, not every address after it. Ports are ignored, as they change
+/// whenever a NAT forgets the peer.
+fn reaches_threshold(previous: &[&str], host: &str, threshold: usize) -> bool {
+    let hosts: BTreeSet<&str> = previous.iter().copied().collect();
+    !hosts.contains(host) && hosts.len() + 1 == threshold
+}
+
+fn disconnected_text(session: &Session, lang: Lang) -> String {
+    let duration = (session.last_handshake_at - session.started_at)
+        .to_std()
+        .unwrap_or_default();
+    let duration = std::time::Duration::from_secs(duration.as_secs());
+    trf(
+        lang,
+        "session_disconnected",
+        &[
+            ("name", &session.profile),
+            ("duration", &humantime::format_duration(duration)),
+            ("rx", &format_bytes(session.rx)),
+            ("tx", &format_bytes(session.tx)),
+        ],
+    )
+}
+
+/// Tells the owner about the session in their language if they opted in.
+/// Failures are only logged, they must not stop the collection.
+async fn notify(bot: &Bot, storage: &StoragePtr, session: &Session, text: impl FnOnce(Lang) -> String) {
+    match storage.get_user(session.user_id).await {
+        Ok(user) if user.notify_sessions => {
+            if let Err(e) = bot.send_message(session.user_id, text(Lang::of(&user))).send().await {
+                tracing::warn!("Could not notify user {} about a session: {}", session.user_id, e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Could not get user {}: {}", session.user_id, e),
+    }
+}
+
+#[test]
+fn test_reaches_threshold() {
+    assert!(reaches_threshold(&["198.51.100.1", "198.51.100.1", "203.0.113.5"], "192.0.2.7", 3));
+    // A known address, or one past the threshold, is not reported again
+    assert!(!reaches_threshold(&["198.51.100.1", "203.0.113.5"], "203.0.113.5", 3));
+    assert!(!reaches_threshold(&["198.51.100.1", "203.0.113.5", "192.0.2.7"], "192.0.2.8", 3));
+    assert!(!reaches_threshold(&["198.51.100.1"], "203.0.113.5", 3));
+}
+
+#[test]
+fn test_transition() {
+    let now = Utc::now();
+    let recent = Some(now - Duration::seconds(30));
+    let stale = Some(now - Duration::minutes(5));
+    assert_eq!(transition(false, recent, now), Transition::Connected);
+    assert_eq!(transition(true, recent, now), Transition::Active);
+    assert_eq!(transition(true, stale, now), Transition::Disconnected);
+    assert_eq!(transition(true, None, now), Transition::Disconnected);
+    assert_eq!(transition(false, stale, now), Transition::Idle);
+    assert_eq!(transition(false, None, now), Transition::Idle);
+}
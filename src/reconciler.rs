@@ -0,0 +1,143 @@
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use clokwerk::{AsyncScheduler, Interval::*};
+use teloxide::prelude::*;
+
+use crate::{
+    bot_metrics::{BotMetrics, BotMetricsPtr},
+    cfg::CfgPtr,
+    control_client::get_clients,
+    fleet::{FleetPtr, Node},
+    rpc::wireguard::Client,
+    storage::StoragePtr,
+    wireguard::diff::{diff_peers, PeerDiff},
+};
+
+/// Brings live peers of the node in line with the database, touching only
+/// the peers which differ.
+async fn reconcile(storage: &StoragePtr, node: &Node, metrics: &BotMetrics) -> Result<PeerDiff> {
+    let name = &node.server.name;
+    // Live peers are fetched first, so a profile changed meanwhile is
+    // reconciled towards its newest state
+    let live: Vec<Client> = node
+        .client
+        .get_statistics(&node.server.interface)
+        .await?
+        .into_iter()
+        .map(|e| Client { key: e.pubkey, ip: e.ip.into() })
+        .collect();
+    let desired = get_clients(storage, node.server.id).await?;
+
+    let diff = diff_peers(&desired, &live);
+    if diff.is_empty() {
+        return Ok(diff);
+    }
+
+    for client in &diff.add {
+        tracing::warn!("Drift on {}: peer {} ({}) is missing", name, client.key, std::net::Ipv4Addr::from(client.ip));
+    }
+    for client in &diff.modify {
+        tracing::warn!("Drift on {}: peer {} should have ip {}", name, client.key, std::net::Ipv4Addr::from(client.ip));
+    }
+    for client in &diff.remove {
+        tracing::warn!("Drift on {}: peer {} ({}) is unknown", name, client.key, std::net::Ipv4Addr::from(client.ip));
+    }
+
+    node.client.update_peers(&node.server.interface, &diff).await?;
+
+    for (action, count) in [("added", diff.add.len()), ("removed", diff.remove.len()), ("modified", diff.modify.len())] {
+        metrics.reconciled_peers.with_label_values(&[name.as_str(), action]).inc_by(count as u64);
+    }
+    Ok(diff)
+}
+
+async fn run_once(
+    bot: &Bot,
+    storage: &StoragePtr,
+    cfg: &CfgPtr,
+    fleet: &FleetPtr,
+    metrics: &BotMetrics,
+    consecutive_drifts: &AtomicU32,
+) {
+    metrics.reconcile_runs.inc();
+    let mut diff = PeerDiff::default();
+    // An unreachable server must not keep the others drifting
+    for node in fleet.nodes() {
+        match reconcile(storage, node, metrics).await {
+            Ok(node_diff) => {
+                diff.add.extend(node_diff.add);
+                diff.remove.extend(node_diff.remove);
+                diff.modify.extend(node_diff.modify);
+            }
+            Err(e) => {
+                metrics.reconcile_failures.with_label_values(&[&node.server.name]).inc();
+                tracing::error!("Reconciliation of {} failed: {}", node.server.name, e);
+            }
+        }
+    }
+
+    if diff.is_empty() {
+        consecutive_drifts.store(0, Ordering::Relaxed);
+        return;
+    }
+
+    metrics.reconcile_drifts.inc();
+    let consecutive = consecutive_drifts.fetch_add(1, Ordering::Relaxed) + 1;
+    tracing::info!(
+        "Reconciled peers: {} added, {} removed, {} modified",
+        diff.add.len(),
+        diff.remove.len(),
+        diff.modify.len()
+    );
+
+    // Alert once per streak, repeated drift means something keeps changing the interface
+    if consecutive == cfg.reconcile_alert_threshold {
+        let text = format!(
+            "Live peers drifted from the database {} times in a row. Last correction: {} added, {} removed, {} modified",
+            consecutive,
+            diff.add.len(),
+            diff.remove.len(),
+            diff.modify.len()
+        );
+        if let Err(e) = bot.send_message(ChatId(cfg.admin_id), text).send().await {
+            tracing::error!("Could not send drift alert: {}", e);
+        }
+    }
+}
+
+pub fn run_reconciler(
+    bot: Bot,
+    storage: StoragePtr,
+    cfg: CfgPtr,
+    fleet: FleetPtr,
+    metrics: BotMetricsPtr,
+) -> Result<tokio::task::JoinHandle<()>> {
+    let interval = humantime::parse_duration(&cfg.reconcile_interval)?;
+    let seconds = u32::try_from(interval.as_secs())
+        .map_err(|_| anyhow!("Reconcile interval {} is too long", cfg.reconcile_interval))?;
+    // Drifts detected in a row, reset by a clean run
+    let consecutive_drifts = Arc::new(AtomicU32::new(0));
+    let mut scheduler = AsyncScheduler::new();
+    scheduler.every(Seconds(seconds)).run(move || {
+        let bot = bot.clone();
+        let storage = storage.clone();
+        let cfg = cfg.clone();
+        let fleet = fleet.clone();
+        let metrics = metrics.clone();
+        let consecutive_drifts = consecutive_drifts.clone();
+        async move { run_once(&bot, &storage, &cfg, &fleet, &metrics, &consecutive_drifts).await }
+    });
+    Ok(tokio::spawn(async move {
+        loop {
+            scheduler.run_pending().await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }))
+}
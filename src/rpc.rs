@@ -1,3 +1,8 @@
 pub mod wireguard {
     tonic::include_proto!("wireguard_control");
+
+    /// Used by the reflection service of `wireguard_control`
+    #[allow(dead_code)]
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("wireguard_control_descriptor");
 }
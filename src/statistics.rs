@@ -1,4 +1,6 @@
-use crate::rpc::wireguard::StatisticsEntry;
+use crate::{rpc::wireguard::StatisticsEntry, wireguard::dump::PeerDump};
+use ipnet::IpNet;
+use chrono::{DateTime, TimeZone, Utc};
 use std::fmt;
 
 #[derive(Debug)]
@@ -8,39 +10,45 @@ pub struct ClientEntry {
     pub latest_handshake: u64,
     pub tx: u64,
     pub rx: u64,
+    /// `ip:port` the peer connected from, if it ever did
+    pub endpoint: Option<String>,
+    pub allowed_ips: Vec<String>,
+    /// Seconds, if persistent keepalive is on
+    pub persistent_keepalive: Option<u32>,
 }
 
-impl std::str::FromStr for ClientEntry {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let entries: Vec<&str> = s.split_ascii_whitespace().collect();
-        if entries.len() != 8 {
-            return Err(format!(
-                "Incorrect input. Entries size should be 8,  not {}",
-                entries.len()
-            ));
+/// Counters are turned around to the side of the peer: `rx` is what it
+/// downloaded. The address is the first IPv4 allowed IP, unspecified if there
+/// is none.
+impl From<PeerDump> for ClientEntry {
+    fn from(peer: PeerDump) -> Self {
+        Self {
+            ip: peer
+                .allowed_ips
+                .iter()
+                .find_map(|ip| match ip {
+                    IpNet::V4(net) => Some(net.addr()),
+                    IpNet::V6(_) => None,
+                })
+                .unwrap_or(std::net::Ipv4Addr::UNSPECIFIED),
+            pubkey: peer.public_key,
+            latest_handshake: peer.latest_handshake,
+            tx: peer.transfer_rx,
+            rx: peer.transfer_tx,
+            endpoint: peer.endpoint.map(|endpoint| endpoint.to_string()),
+            allowed_ips: peer.allowed_ips.iter().map(|ip| ip.to_string()).collect(),
+            persistent_keepalive: peer.persistent_keepalive.map(u32::from),
         }
+    }
+}
 
-        let client_entry = ClientEntry {
-            pubkey: entries[0].into(),
-            ip: entries[3]
-                .strip_suffix("/32")
-                .unwrap_or("1.2.3.4")
-                .parse()
-                .map_err(|e| format!("Could not parse ip address: {}", e))?,
-            latest_handshake: entries[4]
-                .parse()
-                .map_err(|e| format!("Could not parse latest_handshake: {}", e))?,
-            tx: entries[5]
-                .parse()
-                .map_err(|e| format!("Could not parse tx: {}", e))?,
-            rx: entries[6]
-                .parse()
-                .map_err(|e| format!("Could not parse rx: {}", e))?,
-        };
-
-        Ok(client_entry)
+impl ClientEntry {
+    /// Time of the latest handshake, if the peer ever connected.
+    pub fn handshake_time(&self) -> Option<DateTime<Utc>> {
+        match self.latest_handshake {
+            0 => None,
+            secs => Utc.timestamp_opt(secs as i64, 0).single(),
+        }
     }
 }
 
@@ -52,6 +60,9 @@ impl std::convert::Into<StatisticsEntry> for ClientEntry {
             latest_handshake: self.latest_handshake,
             tx: self.tx,
             rx: self.rx,
+            endpoint: self.endpoint.unwrap_or_default(),
+            allowed_ips: self.allowed_ips,
+            persistent_keepalive: self.persistent_keepalive.unwrap_or_default(),
         }
     }
 }
@@ -64,10 +75,20 @@ impl std::convert::From<StatisticsEntry> for ClientEntry {
             latest_handshake: value.latest_handshake,
             tx: value.tx,
             rx: value.rx,
+            endpoint: Some(value.endpoint).filter(|endpoint| !endpoint.is_empty()),
+            allowed_ips: value.allowed_ips,
+            persistent_keepalive: Some(value.persistent_keepalive).filter(|&keepalive| keepalive != 0),
         }
     }
 }
 
+/// Address of an endpoint without the port, "[2001:db8::1]:51820" gives
+/// "2001:db8::1".
+pub fn endpoint_host(endpoint: &str) -> &str {
+    let host = endpoint.rsplit_once(':').map(|(host, _)| host).unwrap_or(endpoint);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
 impl fmt::Display for ClientEntry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let tx = byte_unit::Byte::from_bytes(self.tx).get_appropriate_unit(true);
@@ -86,8 +107,94 @@ impl fmt::Display for ClientEntry {
         };
         write!(
             f,
-            "IP: {}, handshake: {}, tx: {}, rx: {}",
-            self.ip, handshake, tx, rx
+            "IP: {}, endpoint: {}, handshake: {}, tx: {}, rx: {}",
+            self.ip,
+            self.endpoint.as_deref().unwrap_or("(none)"),
+            handshake,
+            tx,
+            rx
         )
     }
 }
+
+/// Peers with a handshake within this many seconds are considered online.
+/// WireGuard renews the session every two minutes while there is traffic.
+pub const ONLINE_HANDSHAKE_AGE: u64 = 180;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Traffic {
+    pub rx: u64,
+    pub tx: u64,
+}
+
+/// Traffic of a profile from the history kept by the statistics collector.
+/// Days and months are in UTC.
+#[derive(Debug, Default)]
+pub struct ProfileUsage {
+    pub today: Traffic,
+    pub month: Traffic,
+    pub total: Traffic,
+    pub latest_handshake: Option<DateTime<Utc>>,
+    /// Address of the latest session
+    pub last_endpoint: Option<String>,
+}
+
+impl ProfileUsage {
+    pub fn is_online(&self, now: DateTime<Utc>) -> bool {
+        self.latest_handshake
+            .map(|handshake| (now - handshake).num_seconds() < ONLINE_HANDSHAKE_AGE as i64)
+            .unwrap_or(false)
+    }
+}
+
+/// Traffic since the previous sample of a peer. Counters going backwards mean
+/// the peer was re-created, so everything it counted so far is new.
+pub fn counter_delta(previous: Option<Traffic>, current: Traffic) -> Traffic {
+    match previous {
+        Some(previous) if current.rx >= previous.rx && current.tx >= previous.tx => Traffic {
+            rx: current.rx - previous.rx,
+            tx: current.tx - previous.tx,
+        },
+        _ => current,
+    }
+}
+
+#[test]
+fn test_endpoint_host() {
+    assert_eq!(endpoint_host("203.0.113.5:51820"), "203.0.113.5");
+    assert_eq!(endpoint_host("[2001:db8::1]:51820"), "2001:db8::1");
+}
+
+#[test]
+fn test_client_entry_from_dump() {
+    let dump = crate::wireguard::dump::parse_dump(
+        "a\t(none)\t[2001:db8::1]:51820\tfd00::2/128,10.0.0.2/32\t1680000000\t10\t20\t25\n\
+         b\t(none)\t(none)\tfd00::3/128\t0\t0\t0\toff",
+    );
+    let entries: Vec<ClientEntry> = dump.peers.into_iter().map(ClientEntry::from).collect();
+    assert_eq!(entries[0].ip, std::net::Ipv4Addr::new(10, 0, 0, 2));
+    assert_eq!(entries[0].endpoint.as_deref(), Some("[2001:db8::1]:51820"));
+    assert_eq!(entries[0].allowed_ips, vec!["fd00::2/128", "10.0.0.2/32"]);
+    assert_eq!((entries[0].rx, entries[0].tx), (20, 10));
+    assert_eq!(entries[0].persistent_keepalive, Some(25));
+    assert_eq!(entries[1].ip, std::net::Ipv4Addr::UNSPECIFIED);
+    assert_eq!(entries[1].persistent_keepalive, None);
+}
+
+#[test]
+fn test_counter_delta() {
+    let traffic = |rx, tx| Traffic { rx, tx };
+    assert_eq!(counter_delta(None, traffic(10, 20)), traffic(10, 20));
+    assert_eq!(counter_delta(Some(traffic(10, 20)), traffic(15, 20)), traffic(5, 0));
+    // Reset of the peer
+    assert_eq!(counter_delta(Some(traffic(10, 20)), traffic(3, 25)), traffic(3, 25));
+}
+
+#[test]
+fn test_profile_usage_is_online() {
+    let now = Utc::now();
+    let usage = |handshake| ProfileUsage { latest_handshake: handshake, ..Default::default() };
+    assert!(usage(Some(now - chrono::Duration::seconds(30))).is_online(now));
+    assert!(!usage(Some(now - chrono::Duration::minutes(10))).is_online(now));
+    assert!(!usage(None).is_online(now));
+}
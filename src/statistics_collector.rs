@@ -1,14 +1,124 @@
-use clokwerk::{AsyncScheduler, TimeUnits, Job, Interval::*};
-use anyhow::anyhow;
-use std::time::Duration;
+use clokwerk::{AsyncScheduler, Interval::*};
+use anyhow::{anyhow, Result};
+use std::{collections::HashMap, time::Duration};
+use teloxide::Bot;
 
-pub fn run_collector() -> tokio::task::JoinHandle<()> {
+use crate::{
+    bot_metrics::{BotMetrics, BotMetricsPtr},
+    cfg::CfgPtr,
+    fleet::FleetPtr,
+    presence,
+    statistics::{ClientEntry, Traffic},
+    storage::{Profile, StoragePtr},
+};
+
+/// Updates gauges of users, profiles and live peers, records the traffic
+/// history of profiles and tracks their sessions. Peers of an unreachable
+/// server are dropped until it answers again, their sessions stay open.
+async fn collect(
+    bot: &Bot,
+    storage: &StoragePtr,
+    cfg: &CfgPtr,
+    fleet: &FleetPtr,
+    metrics: &BotMetrics,
+) -> Result<()> {
+    metrics.users.reset();
+    for (status, count) in storage.count_users_by_status().await? {
+        let status = format!("{:?}", status).to_lowercase();
+        metrics.users.with_label_values(&[&status]).set(count);
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    metrics.peer_rx_bytes.reset();
+    metrics.peer_tx_bytes.reset();
+    metrics.peer_handshake_age.reset();
+    for node in fleet.nodes() {
+        let server = &node.server;
+        let profiles: HashMap<String, _> = match storage.get_server_profiles(server.id).await {
+            Ok(profiles) => profiles.into_iter().map(|p| (p.public_key.clone(), p)).collect(),
+            Err(e) => {
+                tracing::warn!("Could not get profiles of {}: {}", server.name, e);
+                continue;
+            }
+        };
+        metrics
+            .profiles
+            .with_label_values(&[&server.name])
+            .set(profiles.len() as i64);
+
+        let entries = match node.client.get_statistics(&server.interface).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::warn!("Could not collect statistics of {}: {}", server.name, e);
+                continue;
+            }
+        };
+        let mut traffic = HashMap::new();
+        for entry in &entries {
+            let (name, user_id) = match profiles.get(&entry.pubkey) {
+                Some(profile) => {
+                    if let Some(delta) = record_counters(storage, profile, entry).await {
+                        traffic.insert(entry.pubkey.clone(), delta);
+                    }
+                    (profile.name.clone(), profile.user_id.to_string())
+                }
+                None => ("(unknown)".into(), "".into()),
+            };
+            let labels = [server.name.as_str(), entry.pubkey.as_str(), name.as_str(), user_id.as_str()];
+            metrics.peer_rx_bytes.with_label_values(&labels).set(entry.rx as i64);
+            metrics.peer_tx_bytes.with_label_values(&labels).set(entry.tx as i64);
+            if entry.latest_handshake != 0 {
+                let age = now.saturating_sub(entry.latest_handshake);
+                metrics.peer_handshake_age.with_label_values(&labels).set(age as i64);
+            }
+        }
+        if let Err(e) = presence::update(bot, storage, cfg, server, &profiles, &entries, &traffic).await {
+            tracing::warn!("Could not update sessions of {}: {}", server.name, e);
+        }
+    }
+    Ok(())
+}
+
+async fn record_counters(storage: &StoragePtr, profile: &Profile, entry: &ClientEntry) -> Option<Traffic> {
+    let counters = Traffic { rx: entry.rx, tx: entry.tx };
+    match storage.record_peer_counters(profile, counters, entry.handshake_time()).await {
+        Ok(delta) => Some(delta),
+        Err(e) => {
+            tracing::warn!("Could not record traffic of {}: {}", profile.public_key, e);
+            None
+        }
+    }
+}
+
+pub fn run_collector(
+    bot: Bot,
+    storage: StoragePtr,
+    cfg: CfgPtr,
+    fleet: FleetPtr,
+    metrics: BotMetricsPtr,
+) -> Result<tokio::task::JoinHandle<()>> {
+    let interval = humantime::parse_duration(&cfg.metrics_interval)?;
+    let seconds = u32::try_from(interval.as_secs())
+        .map_err(|_| anyhow!("Metrics interval {} is too long", cfg.metrics_interval))?;
     let mut scheduler = AsyncScheduler::new();
-    scheduler.every(1.minute()).run(|| async {});
-    tokio::spawn(async move {
+    scheduler.every(Seconds(seconds)).run(move || {
+        let bot = bot.clone();
+        let storage = storage.clone();
+        let cfg = cfg.clone();
+        let fleet = fleet.clone();
+        let metrics = metrics.clone();
+        async move {
+            if let Err(e) = collect(&bot, &storage, &cfg, &fleet, &metrics).await {
+                tracing::error!("Statistics collection failed: {}", e);
+            }
+        }
+    });
+    Ok(tokio::spawn(async move {
         loop {
             scheduler.run_pending().await;
-            tokio::time::sleep(Duration::from_secs(30)).await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
         }
-    })
+    }))
 }
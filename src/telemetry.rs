@@ -0,0 +1,62 @@
+use anyhow::Result;
+use opentelemetry::{
+    global,
+    sdk::{propagation::TraceContextPropagator, trace as sdktrace, Resource},
+    trace::TracerProvider as _,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tracing_subscriber::{prelude::*, registry::Registry, EnvFilter};
+
+use crate::cfg::{LogCfg, LogFormat};
+
+/// Installs logging and tracing. Spans always get trace ids, so that they are
+/// propagated to the control service, but are exported only if an OTLP
+/// endpoint is configured.
+pub fn init(cfg: &LogCfg, service_name: &'static str) -> Result<()> {
+    let filter = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(&cfg.level))?;
+    let fmt_layer = match cfg.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_target(false).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+
+    let trace_config = sdktrace::config()
+        .with_resource(Resource::new(vec![KeyValue::new("service.name", service_name)]));
+    let tracer = match &cfg.otlp_endpoint {
+        Some(endpoint) => opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint.clone()),
+            )
+            .with_trace_config(trace_config)
+            .install_batch(opentelemetry::runtime::Tokio)?,
+        None => {
+            let provider = sdktrace::TracerProvider::builder()
+                .with_config(trace_config)
+                .build();
+            let tracer = provider.tracer(service_name);
+            // The global provider keeps the tracer alive
+            global::set_tracer_provider(provider);
+            tracer
+        }
+    };
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    Registry::default()
+        .with(fmt_layer)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .with(filter)
+        .try_init()?;
+    Ok(())
+}
+
+/// Flushes spans which are not exported yet.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use execute::Execute;
+use tonic::Status;
+
+use crate::{
+    rpc::wireguard::Client,
+    wireguard::config::parse_server_config,
+};
+
+/// What the control service does to WireGuard interfaces. Arguments are
+/// validated by the caller, backends may pass them to a shell as is.
+pub trait Backend: Send + Sync {
+    fn exists(&self, interface: &str) -> bool;
+    /// Brings the interface up from its config file.
+    fn up(&self, interface: &str, config_path: &Path) -> Result<(), Status>;
+    fn down(&self, interface: &str, config_path: &Path) -> Result<(), Status>;
+    /// Applies the config file to the running interface without restarting it.
+    fn sync(&self, interface: &str, config_path: &Path) -> Result<(), Status>;
+    fn set_peer(&self, interface: &str, peer: &Client) -> Result<(), Status>;
+    fn remove_peer(&self, interface: &str, key: &str) -> Result<(), Status>;
+    /// Same format as `wg show <interface> dump`.
+    fn dump(&self, interface: &str) -> Result<String, Status>;
+}
+
+pub fn run(command: &str, action: &str) -> Result<(), Status> {
+    let mut cmd = execute::shell(command);
+    let result = cmd
+        .execute()
+        .map_err(|e| Status::internal(format!("Failed to {}: {}", action, e)))?;
+    let exit_code = result.unwrap_or(0);
+    if exit_code != 0 {
+        return Err(Status::internal(format!(
+            "Failed to {}, non-successed exit status: {}",
+            action, exit_code
+        )));
+    }
+    Ok(())
+}
+
+/// Runs `wg` and `wg-quick`. `wg-quick` gets the full path of the config, so
+/// any config directory works; the interface is named after the file.
+pub struct ShellBackend;
+
+impl Backend for ShellBackend {
+    fn exists(&self, interface: &str) -> bool {
+        Path::new("/sys/class/net").join(interface).exists()
+    }
+
+    fn up(&self, interface: &str, config_path: &Path) -> Result<(), Status> {
+        run(&format!("wg-quick up {}", config_path.display()), &format!("up {} interface", interface))
+    }
+
+    fn down(&self, interface: &str, config_path: &Path) -> Result<(), Status> {
+        run(&format!("wg-quick down {}", config_path.display()), &format!("down {} interface", interface))
+    }
+
+    fn sync(&self, interface: &str, config_path: &Path) -> Result<(), Status> {
+        run(
+            &format!("wg syncconf {} <(wg-quick strip {})", interface, config_path.display()),
+            "sync config",
+        )
+    }
+
+    fn set_peer(&self, interface: &str, peer: &Client) -> Result<(), Status> {
+        run(
+            &format!(
+                "wg set {} peer {} allowed-ips {}/32",
+                interface,
+                peer.key,
+                std::net::Ipv4Addr::from(peer.ip)
+            ),
+            "set peer",
+        )
+    }
+
+    fn remove_peer(&self, interface: &str, key: &str) -> Result<(), Status> {
+        run(&format!("wg set {} peer {} remove", interface, key), "remove peer")
+    }
+
+    fn dump(&self, interface: &str) -> Result<String, Status> {
+        let mut cmd = execute::shell(format!("wg show {} dump", interface));
+        let output = cmd
+            .output()
+            .map_err(|e| Status::internal(format!("Could not get statistics info: {}", e)))?;
+        String::from_utf8(output.stdout)
+            .map_err(|e| Status::internal(format!("Could not get string from output: {}", e)))
+    }
+}
+
+#[derive(Default)]
+struct MockPeer {
+    ip: u32,
+    tx: u64,
+    rx: u64,
+}
+
+#[derive(Default)]
+struct MockInterface {
+    listen_port: String,
+    peers: HashMap<String, MockPeer>,
+}
+
+/// Keeps interfaces in memory, so that several control services can run on
+/// one machine without WireGuard. Every peer looks connected and its traffic
+/// grows on each dump.
+#[derive(Default)]
+pub struct MockBackend {
+    interfaces: Mutex<HashMap<String, MockInterface>>,
+}
+
+impl MockBackend {
+    fn load(&self, interface: &str, config_path: &Path, create: bool) -> Result<(), Status> {
+        let text = std::fs::read_to_string(config_path)
+            .map_err(|e| Status::internal(format!("Failed to read config: {}", e)))?;
+        let config = parse_server_config(&text).map_err(Status::internal)?;
+
+        let mut interfaces = self.interfaces.lock().unwrap();
+        let state = match interfaces.get_mut(interface) {
+            Some(state) => state,
+            None if create => interfaces.entry(interface.to_owned()).or_default(),
+            None => return Err(Status::failed_precondition(format!("{} is down", interface))),
+        };
+        state.listen_port = config
+            .interface
+            .iter()
+            .find(|(field, _)| field == "ListenPort")
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+        let mut peers = std::mem::take(&mut state.peers);
+        for peer in config.peers {
+            let mut current = peers.remove(&peer.key).unwrap_or_default();
+            current.ip = peer.ip;
+            state.peers.insert(peer.key, current);
+        }
+        Ok(())
+    }
+
+    fn with_interface<T>(
+        &self,
+        interface: &str,
+        f: impl FnOnce(&mut MockInterface) -> T,
+    ) -> Result<T, Status> {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        let state = interfaces
+            .get_mut(interface)
+            .ok_or(Status::failed_precondition(format!("{} is down", interface)))?;
+        Ok(f(state))
+    }
+}
+
+impl Backend for MockBackend {
+    fn exists(&self, interface: &str) -> bool {
+        self.interfaces.lock().unwrap().contains_key(interface)
+    }
+
+    fn up(&self, interface: &str, config_path: &Path) -> Result<(), Status> {
+        if self.exists(interface) {
+            return Err(Status::failed_precondition(format!("{} already exists", interface)));
+        }
+        self.load(interface, config_path, true)
+    }
+
+    fn down(&self, interface: &str, _config_path: &Path) -> Result<(), Status> {
+        self.interfaces
+            .lock()
+            .unwrap()
+            .remove(interface)
+            .map(|_| ())
+            .ok_or(Status::failed_precondition(format!("{} is down", interface)))
+    }
+
+    fn sync(&self, interface: &str, config_path: &Path) -> Result<(), Status> {
+        self.load(interface, config_path, false)
+    }
+
+    fn set_peer(&self, interface: &str, peer: &Client) -> Result<(), Status> {
+        self.with_interface(interface, |state| {
+            state.peers.entry(peer.key.clone()).or_default().ip = peer.ip;
+        })
+    }
+
+    fn remove_peer(&self, interface: &str, key: &str) -> Result<(), Status> {
+        self.with_interface(interface, |state| {
+            state.peers.remove(key);
+        })
+    }
+
+    fn dump(&self, interface: &str) -> Result<String, Status> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        self.with_interface(interface, |state| {
+            let mut dump = format!("(hidden) (none) {} off\n", state.listen_port);
+            for (key, peer) in state.peers.iter_mut() {
+                peer.tx += rand::random::<u16>() as u64;
+                peer.rx += rand::random::<u16>() as u64 * 8;
+                dump.push_str(&format!(
+                    "{} (none) (none) {}/32 {} {} {} off\n",
+                    key,
+                    std::net::Ipv4Addr::from(peer.ip),
+                    now,
+                    peer.tx,
+                    peer.rx
+                ));
+            }
+            dump
+        })
+    }
+}
+
+#[test]
+fn test_mock_backend() {
+    let dir = std::env::temp_dir().join(format!("wg-mock-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("wg0.conf");
+    std::fs::write(
+        &path,
+        "[Interface]\nAddress = 10.9.0.1/24\nListenPort = 51820\n\n[Peer]\nPublicKey = a\nAllowedIPs = 10.9.0.2/32\n",
+    )
+    .unwrap();
+
+    let backend = MockBackend::default();
+    assert!(backend.sync("wg0", &path).is_err());
+    backend.up("wg0", &path).unwrap();
+    assert!(backend.exists("wg0"));
+
+    backend
+        .set_peer("wg0", &Client { key: "b".into(), ip: std::net::Ipv4Addr::new(10, 9, 0, 3).into() })
+        .unwrap();
+    backend.remove_peer("wg0", "a").unwrap();
+    let dump = backend.dump("wg0").unwrap();
+    let mut lines = dump.lines();
+    assert_eq!(lines.next().unwrap().split_ascii_whitespace().nth(2), Some("51820"));
+    assert!(lines.next().unwrap().starts_with("b (none) (none) 10.9.0.3/32"));
+
+    backend.down("wg0", &path).unwrap();
+    assert!(!backend.exists("wg0"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
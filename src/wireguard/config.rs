@@ -1,7 +1,7 @@
 use crate::{
     cfg::CfgPtr,
     rpc::wireguard::{Client, Server},
-    storage::Profile,
+    storage::{Profile, VpnServer},
 };
 use serde::Serialize;
 use tinytemplate::TinyTemplate;
@@ -42,7 +42,7 @@ struct PeerConfigCtx {
     server_public_key: String,
     endpoint: String,
     port: u16,
-    only_local: bool,
+    allowed_ips: String,
     dns: String,
 }
 
@@ -53,7 +53,7 @@ DNS = {dns}
 
 [Peer]
 PublicKey = {server_public_key}
-AllowedIPs = {{if not only_local }}0.0.0.0/0{{ else }}10.9.0.0/24{{ endif }}
+AllowedIPs = {allowed_ips}
 Endpoint = {endpoint}:{port}";
 
 pub fn build_server_config(server: &Server, clients: &Vec<Client>) -> Result<String, Status> {
@@ -95,19 +95,104 @@ pub fn build_server_config(server: &Server, clients: &Vec<Client>) -> Result<Str
     Ok(config)
 }
 
+/// Server config read back from `wg0.conf`. Interface fields keep the order
+/// of the file.
+#[derive(Debug, Default, PartialEq)]
+pub struct ParsedServerConfig {
+    pub interface: Vec<(String, String)>,
+    pub peers: Vec<Client>,
+}
+
+impl ParsedServerConfig {
+    /// Config file with the same interface and peers. Comments and unknown
+    /// peer fields are not kept.
+    pub fn render(&self) -> String {
+        let mut content = String::from("[Interface]\n");
+        for (field, value) in &self.interface {
+            content.push_str(&format!("{} = {}\n", field, value));
+        }
+        for peer in &self.peers {
+            content.push_str(&format!(
+                "\n[Peer]\nPublicKey = {}\nAllowedIPs = {}/32\n",
+                peer.key,
+                std::net::Ipv4Addr::from(peer.ip)
+            ));
+        }
+        content
+    }
+}
+
+pub fn parse_server_config(text: &str) -> Result<ParsedServerConfig, String> {
+    let mut config = ParsedServerConfig::default();
+    let mut section = "";
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = &line[1..line.len() - 1];
+            if section == "Peer" {
+                config.peers.push(Client::default());
+            }
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .ok_or(format!("Line {}: expected `Key = Value`", idx + 1))?;
+        match section {
+            "Interface" => config.interface.push((key.to_owned(), value.to_owned())),
+            "Peer" => {
+                let peer = config.peers.last_mut().unwrap();
+                match key {
+                    "PublicKey" => peer.key = value.to_owned(),
+                    "AllowedIPs" => {
+                        let ip: std::net::Ipv4Addr = value
+                            .split('/')
+                            .next()
+                            .unwrap_or_default()
+                            .parse()
+                            .map_err(|e| format!("Line {}: invalid AllowedIPs: {}", idx + 1, e))?;
+                        peer.ip = ip.into();
+                    }
+                    _ => {}
+                }
+            }
+            _ => return Err(format!("Line {}: field outside of a section", idx + 1)),
+        }
+    }
+    Ok(config)
+}
+
 pub struct PeerConfig {
     endpoint: String,
     key: String,
     ip: u32,
     port: u16,
     dns: Vec<u32>,
+    allowed_ips: String,
     public_key: String,
 }
 
 impl PeerConfig {
-    pub fn new(profile: &Profile, cfg: &CfgPtr) -> anyhow::Result<Self> {
-        let endpoint_is_ip = cfg.endpoint.parse::<std::net::Ipv4Addr>().is_ok();
-        let endpoint_is_domain = url::Url::parse(&cfg.endpoint).is_ok();
+    pub fn new(
+        profile: &Profile,
+        cfg: &CfgPtr,
+        server: &VpnServer,
+        server_public_key: &str,
+    ) -> anyhow::Result<Self> {
+        let interface = cfg.interface(&server.interface)?;
+        let dns = interface
+            .dns
+            .iter()
+            .map(|ip| ip.parse::<std::net::Ipv4Addr>().map(u32::from))
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|e| anyhow::anyhow!("Invalid DNS of interface {}: {}", interface.name, e))?;
+
+        let endpoint_is_ip = server.endpoint.parse::<std::net::Ipv4Addr>().is_ok();
+        let endpoint_is_domain = url::Url::parse(&server.endpoint).is_ok();
         if !endpoint_is_ip && !endpoint_is_domain {
             return Err(anyhow::anyhow!("Invalid endpoint"));
         }
@@ -121,10 +206,11 @@ impl PeerConfig {
         Ok(Self {
             ip,
             key: profile.private_key.clone(),
-            endpoint: cfg.endpoint.clone(),
-            port: cfg.port,
-            dns: vec![std::net::Ipv4Addr::new(8, 8, 8, 8).into()],
-            public_key: cfg.public_key.clone(),
+            endpoint: server.endpoint.clone(),
+            port: interface.port,
+            dns,
+            allowed_ips: interface.allowed_ips.clone(),
+            public_key: server_public_key.to_owned(),
         })
     }
 }
@@ -137,7 +223,7 @@ pub fn build_peer_config(peer_cfg: &PeerConfig) -> Result<String, tinytemplate::
         peer_private_key: peer_cfg.key.clone(),
         server_public_key: peer_cfg.public_key.clone(),
         endpoint: peer_cfg.endpoint.clone(),
-        only_local: false,
+        allowed_ips: peer_cfg.allowed_ips.clone(),
         port: peer_cfg.port,
         dns: peer_cfg
             .dns
@@ -175,6 +261,31 @@ fn test_build_server_config() {
     println!("{}", res);
 }
 
+#[test]
+fn test_parse_server_config() {
+    let server = Server {
+        key: "YE3x5BL8N36oPZ9N2HbQIrPPGI+b+Qk86TjrU+FJonU=".into(),
+        ip: std::net::Ipv4Addr::new(10, 9, 0, 1).into(),
+        port: 51820,
+        subnet: 24,
+        dns: vec![],
+        post_up: "iptables -t nat -I POSTROUTING -o eth0 -j MASQUERADE".into(),
+        pre_down: "iptables -t nat -D POSTROUTING -o eth0 -j MASQUERADE".into(),
+    };
+    let clients: Vec<Client> = vec![Client {
+        ip: std::net::Ipv4Addr::new(10, 9, 0, 2).into(),
+        key: "qMzUy9H0ISe8AMNIs2Pm+RmVYdUxn9b3XfEAOILTfVA=".into(),
+    }];
+
+    let text = build_server_config(&server, &clients).expect("Could not build server config");
+    let parsed = parse_server_config(&text).expect("Could not parse server config");
+    assert_eq!(parsed.peers, clients);
+    assert_eq!(parsed.interface[0], ("Address".to_string(), "10.9.0.1/24".to_string()));
+    assert!(parsed.interface.contains(&("ListenPort".to_string(), "51820".to_string())));
+    assert_eq!(parse_server_config(&parsed.render()).unwrap(), parsed);
+    assert!(parse_server_config("Address = 10.9.0.1/24").is_err());
+}
+
 #[test]
 fn test_build_peer_config() {
     let cfg = PeerConfig {
@@ -186,9 +297,11 @@ fn test_build_peer_config() {
             std::net::Ipv4Addr::new(8, 8, 8, 8).into(),
             std::net::Ipv4Addr::new(1, 1, 1, 1).into(),
         ],
+        allowed_ips: "10.9.0.0/24, 192.168.1.0/24".into(),
         public_key: "vvM86VntTg9J4XhtDd3tRN0XS6zUS+6OgiwTmx+FeEk=".into(),
     };
 
     let res = build_peer_config(&cfg).expect("Could not build peer config");
     println!("{}", res);
+    assert!(res.contains("AllowedIPs = 10.9.0.0/24, 192.168.1.0/24"));
 }
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use crate::rpc::wireguard::Client;
+
+/// Changes needed to turn one set of peers into another. Peers are matched
+/// by public key, so a changed address is a modification.
+#[derive(Debug, Default, PartialEq)]
+pub struct PeerDiff {
+    pub add: Vec<Client>,
+    pub remove: Vec<Client>,
+    pub modify: Vec<Client>,
+}
+
+impl PeerDiff {
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.remove.is_empty() && self.modify.is_empty()
+    }
+}
+
+/// Interface field which differs between configs. Private keys are never
+/// put into it.
+#[derive(Debug, PartialEq)]
+pub struct FieldChange {
+    pub field: String,
+    pub current: String,
+    pub desired: String,
+}
+
+pub fn diff_interface(current: &[(String, String)], desired: &[(String, String)]) -> Vec<FieldChange> {
+    let find = |fields: &[(String, String)], name: &str| {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default()
+    };
+
+    let mut names: Vec<&String> = desired.iter().map(|(field, _)| field).collect();
+    names.extend(current.iter().map(|(field, _)| field).filter(|f| !desired.iter().any(|(d, _)| d == *f)));
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let (current, desired) = (find(current, name), find(desired, name));
+            if current == desired {
+                return None;
+            }
+            let (current, desired) = if name == "PrivateKey" {
+                ("(hidden)".to_owned(), "(hidden, changed)".to_owned())
+            } else {
+                (current, desired)
+            };
+            Some(FieldChange { field: name.clone(), current, desired })
+        })
+        .collect()
+}
+
+pub fn diff_peers(desired: &[Client], current: &[Client]) -> PeerDiff {
+    let current_ips: HashMap<&str, u32> = current.iter().map(|c| (c.key.as_str(), c.ip)).collect();
+    let desired_ips: HashMap<&str, u32> = desired.iter().map(|c| (c.key.as_str(), c.ip)).collect();
+
+    let mut diff = PeerDiff::default();
+    for client in desired {
+        match current_ips.get(client.key.as_str()) {
+            None => diff.add.push(client.clone()),
+            Some(ip) if *ip != client.ip => diff.modify.push(client.clone()),
+            Some(_) => {}
+        }
+    }
+    for client in current {
+        if !desired_ips.contains_key(client.key.as_str()) {
+            diff.remove.push(client.clone());
+        }
+    }
+    diff
+}
+
+#[test]
+fn test_diff_peers() {
+    let client = |key: &str, ip: [u8; 4]| Client {
+        key: key.into(),
+        ip: std::net::Ipv4Addr::from(ip).into(),
+    };
+    let desired = vec![client("a", [10, 9, 0, 2]), client("b", [10, 9, 0, 3]), client("c", [10, 9, 0, 4])];
+    let current = vec![client("a", [10, 9, 0, 2]), client("b", [10, 9, 0, 5]), client("d", [10, 9, 0, 6])];
+
+    let diff = diff_peers(&desired, &current);
+    assert_eq!(diff.add, vec![client("c", [10, 9, 0, 4])]);
+    assert_eq!(diff.modify, vec![client("b", [10, 9, 0, 3])]);
+    assert_eq!(diff.remove, vec![client("d", [10, 9, 0, 6])]);
+
+    assert!(diff_peers(&desired, &desired).is_empty());
+}
+
+#[test]
+fn test_diff_interface() {
+    let fields = |items: &[(&str, &str)]| -> Vec<(String, String)> {
+        items.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    };
+    let current = fields(&[("Address", "10.9.0.1/24"), ("ListenPort", "51820"), ("PrivateKey", "a"), ("Table", "off")]);
+    let desired = fields(&[("Address", "10.9.0.1/24"), ("ListenPort", "51821"), ("PrivateKey", "b")]);
+
+    let changes = diff_interface(&current, &desired);
+    assert_eq!(changes.len(), 3);
+    assert_eq!(changes[0], FieldChange { field: "ListenPort".into(), current: "51820".into(), desired: "51821".into() });
+    assert_eq!(changes[1].field, "PrivateKey");
+    assert!(!changes[1].current.contains('a') && !changes[1].desired.contains('b'));
+    assert_eq!(changes[2], FieldChange { field: "Table".into(), current: "off".into(), desired: "".into() });
+}
@@ -0,0 +1,263 @@
+use std::{fmt, net::SocketAddr};
+
+use ipnet::IpNet;
+
+/// First line of `wg show <interface> dump`.
+#[derive(Debug, PartialEq)]
+pub struct InterfaceDump {
+    pub public_key: String,
+    pub listen_port: u16,
+    pub fwmark: Option<u32>,
+}
+
+/// Peer line of `wg show <interface> dump`. Transfers are counted by the
+/// interface, so `transfer_rx` is what the peer sent.
+#[derive(Debug, PartialEq)]
+pub struct PeerDump {
+    pub public_key: String,
+    pub has_preshared_key: bool,
+    pub endpoint: Option<SocketAddr>,
+    pub allowed_ips: Vec<IpNet>,
+    /// Seconds since the epoch, 0 if the peer never connected
+    pub latest_handshake: u64,
+    pub transfer_rx: u64,
+    pub transfer_tx: u64,
+    pub persistent_keepalive: Option<u16>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DumpError {
+    /// Neither an interface line of 4 fields nor a peer line of 8
+    FieldCount(usize),
+    InvalidEndpoint(String),
+    InvalidAllowedIp(String),
+    InvalidNumber { field: &'static str, value: String },
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FieldCount(count) => write!(f, "Expected 4 or 8 fields, found {}", count),
+            Self::InvalidEndpoint(value) => write!(f, "Invalid endpoint {}", value),
+            Self::InvalidAllowedIp(value) => write!(f, "Invalid allowed IP {}", value),
+            Self::InvalidNumber { field, value } => write!(f, "Invalid {} {}", field, value),
+        }
+    }
+}
+
+impl std::error::Error for DumpError {}
+
+/// Error of a line, numbered from 1.
+#[derive(Debug, PartialEq)]
+pub struct LineError {
+    pub line: usize,
+    pub error: DumpError,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.error)
+    }
+}
+
+/// Everything which could be parsed. A malformed line only ends up in
+/// `errors`, the other peers are still reported.
+#[derive(Debug, Default, PartialEq)]
+pub struct Dump {
+    pub interface: Option<InterfaceDump>,
+    pub peers: Vec<PeerDump>,
+    pub errors: Vec<LineError>,
+}
+
+pub fn parse_dump(text: &str) -> Dump {
+    let mut dump = Dump::default();
+    for (idx, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split_ascii_whitespace().collect();
+        let result = match fields.len() {
+            0 => continue,
+            4 => parse_interface(&fields).map(|interface| dump.interface = Some(interface)),
+            8 => parse_peer(&fields).map(|peer| dump.peers.push(peer)),
+            count => Err(DumpError::FieldCount(count)),
+        };
+        if let Err(error) = result {
+            dump.errors.push(LineError { line: idx + 1, error });
+        }
+    }
+    dump
+}
+
+fn parse_interface(fields: &[&str]) -> Result<InterfaceDump, DumpError> {
+    Ok(InterfaceDump {
+        public_key: fields[1].to_owned(),
+        listen_port: number("listen port", fields[2])?,
+        fwmark: match fields[3] {
+            "off" => None,
+            value => Some(match value.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).map_err(|_| invalid("fwmark", value))?,
+                None => number("fwmark", value)?,
+            }),
+        },
+    })
+}
+
+fn parse_peer(fields: &[&str]) -> Result<PeerDump, DumpError> {
+    Ok(PeerDump {
+        public_key: fields[0].to_owned(),
+        has_preshared_key: fields[1] != "(none)",
+        endpoint: match fields[2] {
+            "(none)" => None,
+            value => Some(value.parse().map_err(|_| DumpError::InvalidEndpoint(value.to_owned()))?),
+        },
+        allowed_ips: match fields[3] {
+            "(none)" => vec![],
+            value => value
+                .split(',')
+                .map(|ip| ip.parse().map_err(|_| DumpError::InvalidAllowedIp(ip.to_owned())))
+                .collect::<Result<_, _>>()?,
+        },
+        latest_handshake: number("latest handshake", fields[4])?,
+        transfer_rx: number("transfer rx", fields[5])?,
+        transfer_tx: number("transfer tx", fields[6])?,
+        persistent_keepalive: match fields[7] {
+            "off" => None,
+            value => Some(number("persistent keepalive", value)?),
+        },
+    })
+}
+
+fn number<T: std::str::FromStr>(field: &'static str, value: &str) -> Result<T, DumpError> {
+    value.parse().map_err(|_| invalid(field, value))
+}
+
+fn invalid(field: &'static str, value: &str) -> DumpError {
+    DumpError::InvalidNumber { field, value: value.to_owned() }
+}
+
+#[cfg(test)]
+const INTERFACE: &str =
+    "WIr6m3WZ9CQ8Ml2G2Xy3YVN6hhwH1wfpEgwUKwvQtkI=\tHIgo9xNzJMWLKASShiTqIybxZ0U3wGLiUeJ1PKf8ykw=\t51820\toff";
+
+#[cfg(test)]
+fn peer(endpoint: Option<&str>, allowed_ips: &[&str], keepalive: Option<u16>) -> PeerDump {
+    PeerDump {
+        public_key: "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=".into(),
+        has_preshared_key: false,
+        endpoint: endpoint.map(|endpoint| endpoint.parse().unwrap()),
+        allowed_ips: allowed_ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+        latest_handshake: 1680000000,
+        transfer_rx: 148000,
+        transfer_tx: 952000,
+        persistent_keepalive: keepalive,
+    }
+}
+
+#[test]
+fn test_parse_peer_lines() {
+    const KEY: &str = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=";
+    let cases: Vec<(String, PeerDump)> = vec![
+        (
+            format!("{KEY}\t(none)\t192.95.5.67:1234\t10.192.122.3/32\t1680000000\t148000\t952000\toff"),
+            peer(Some("192.95.5.67:1234"), &["10.192.122.3/32"], None),
+        ),
+        (
+            format!("{KEY}\t(none)\t[2607:5300:60:6b0::c05f:543]:2468\t10.192.122.4/32,fd00::4/128\t1680000000\t148000\t952000\t25"),
+            peer(Some("[2607:5300:60:6b0::c05f:543]:2468"), &["10.192.122.4/32", "fd00::4/128"], Some(25)),
+        ),
+        // Site to site peer routing whole networks
+        (
+            format!("{KEY}\t(none)\t203.0.113.9:51820\t10.0.0.0/8,192.168.0.0/16\t1680000000\t148000\t952000\toff"),
+            peer(Some("203.0.113.9:51820"), &["10.0.0.0/8", "192.168.0.0/16"], None),
+        ),
+        (
+            format!("{KEY}\t(none)\t(none)\t(none)\t1680000000\t148000\t952000\toff"),
+            peer(None, &[], None),
+        ),
+        (
+            format!("{KEY}\tFpCyhws9cxwWoV4xELtfJvjJN+zQVRPISllRWgeopVE=\t(none)\t10.0.0.2/32\t1680000000\t148000\t952000\toff"),
+            PeerDump { has_preshared_key: true, ..peer(None, &["10.0.0.2/32"], None) },
+        ),
+    ];
+    for (line, expected) in cases {
+        let dump = parse_dump(&line);
+        assert!(dump.errors.is_empty(), "{}", line);
+        assert_eq!(dump.peers, vec![expected], "{}", line);
+    }
+
+    // Peer which never connected
+    let dump = parse_dump(&format!("{KEY}\t(none)\t(none)\t10.0.0.5/32\t0\t0\t0\toff"));
+    assert_eq!(dump.peers[0].latest_handshake, 0);
+    assert_eq!(dump.peers[0].endpoint, None);
+}
+
+#[test]
+fn test_parse_interface_line() {
+    let cases = vec![
+        (INTERFACE.to_owned(), None),
+        (INTERFACE.replace("off", "0xca6c"), Some(0xca6c)),
+        (INTERFACE.replace("off", "51820"), Some(51820)),
+    ];
+    for (line, fwmark) in cases {
+        let dump = parse_dump(&line);
+        assert!(dump.errors.is_empty());
+        assert_eq!(
+            dump.interface,
+            Some(InterfaceDump {
+                public_key: "HIgo9xNzJMWLKASShiTqIybxZ0U3wGLiUeJ1PKf8ykw=".into(),
+                listen_port: 51820,
+                fwmark,
+            })
+        );
+    }
+}
+
+#[test]
+fn test_parse_errors() {
+    let cases = vec![
+        ("a\t(none)\t(none)\t10.0.0.2/32\t0\t0\t0", DumpError::FieldCount(7)),
+        ("a\t(none)\t1.2.3.4:99999\t10.0.0.2/32\t0\t0\t0\toff", DumpError::InvalidEndpoint("1.2.3.4:99999".into())),
+        ("a\t(none)\t1.2.3.4\t10.0.0.2/32\t0\t0\t0\toff", DumpError::InvalidEndpoint("1.2.3.4".into())),
+        ("a\t(none)\t(none)\t10.0.0.2/32,10.0.0.300/32\t0\t0\t0\toff", DumpError::InvalidAllowedIp("10.0.0.300/32".into())),
+        (
+            "a\t(none)\t(none)\t10.0.0.2/32\t0\tabc\t0\toff",
+            DumpError::InvalidNumber { field: "transfer rx", value: "abc".into() },
+        ),
+        (
+            "a\t(none)\t(none)\t10.0.0.2/32\t-1\t0\t0\toff",
+            DumpError::InvalidNumber { field: "latest handshake", value: "-1".into() },
+        ),
+        (
+            "a\t(none)\t(none)\t10.0.0.2/32\t0\t0\t0\talways",
+            DumpError::InvalidNumber { field: "persistent keepalive", value: "always".into() },
+        ),
+        ("(hidden)\tb\t70000\toff", DumpError::InvalidNumber { field: "listen port", value: "70000".into() }),
+        ("(hidden)\tb\t51820\t0xzz", DumpError::InvalidNumber { field: "fwmark", value: "0xzz".into() }),
+    ];
+    for (line, error) in cases {
+        assert_eq!(parse_dump(line).errors, vec![LineError { line: 1, error }], "{}", line);
+    }
+}
+
+#[test]
+fn test_parse_partial_dump() {
+    let text = format!(
+        "{INTERFACE}\n\
+         a\t(none)\t198.51.100.1:4000\t10.0.0.2/32\t1680000000\t10\t20\toff\n\
+         b\t(none)\tgarbage\t10.0.0.3/32\t1680000000\t10\t20\toff\n\
+         \n\
+         c\t(none)\t(none)\t10.0.0.4/32\t0\t0\t0\t25\n"
+    );
+    let dump = parse_dump(&text);
+    assert_eq!(dump.interface.unwrap().listen_port, 51820);
+    let keys: Vec<&str> = dump.peers.iter().map(|peer| peer.public_key.as_str()).collect();
+    assert_eq!(keys, vec!["a", "c"]);
+    assert_eq!(
+        dump.errors,
+        vec![LineError { line: 3, error: DumpError::InvalidEndpoint("garbage".into()) }]
+    );
+    assert_eq!(dump.errors[0].to_string(), "Line 3: Invalid endpoint garbage");
+
+    // What the mock backend prints
+    let dump = parse_dump("(hidden) (none) 51820 off\nb (none) (none) 10.9.0.3/32 1680000000 5 40 off\n");
+    assert!(dump.errors.is_empty());
+    assert_eq!(dump.peers.len(), 1);
+}
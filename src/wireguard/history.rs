@@ -0,0 +1,148 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Writes `content` to a temporary file next to `path` and renames it over
+/// `path`, so readers see either the old or the new file, never a truncated one.
+pub fn write_atomic(path: &Path, content: &str) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let file_name = path
+        .file_name()
+        .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+    let tmp_path = dir.join(format!(".{}.tmp", file_name.to_string_lossy()));
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)?;
+    fs::File::open(dir)?.sync_all()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ConfigVersion {
+    pub id: String,
+    /// Unix time in milliseconds
+    pub timestamp: u64,
+    pub size: u64,
+}
+
+/// Ring of the last `keep` applied configs, stored as `<timestamp>.conf`
+/// files in `dir`.
+pub struct ConfigHistory {
+    dir: PathBuf,
+    keep: usize,
+}
+
+impl ConfigHistory {
+    pub fn new(dir: impl Into<PathBuf>, keep: usize) -> Self {
+        Self { dir: dir.into(), keep }
+    }
+
+    pub fn save(&self, content: &str) -> io::Result<ConfigVersion> {
+        fs::create_dir_all(&self.dir)?;
+        let mut timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        // Keep ids unique and ordered if two versions are saved within a millisecond
+        if let Some(latest) = self.list()?.first() {
+            timestamp = timestamp.max(latest.timestamp + 1);
+        }
+
+        let id = timestamp.to_string();
+        write_atomic(&self.path(&id), content)?;
+        self.prune()?;
+        Ok(ConfigVersion {
+            id,
+            timestamp,
+            size: content.len() as u64,
+        })
+    }
+
+    /// Newest version first.
+    pub fn list(&self) -> io::Result<Vec<ConfigVersion>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut versions = vec![];
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let timestamp = match name.strip_suffix(".conf").and_then(|id| id.parse::<u64>().ok()) {
+                Some(timestamp) => timestamp,
+                None => continue,
+            };
+            versions.push(ConfigVersion {
+                id: timestamp.to_string(),
+                timestamp,
+                size: entry.metadata()?.len(),
+            });
+        }
+        versions.sort_by_key(|version| std::cmp::Reverse(version.timestamp));
+        Ok(versions)
+    }
+
+    pub fn read(&self, id: &str) -> io::Result<String> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid version id"));
+        }
+        fs::read_to_string(self.path(id))
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        for version in self.list()?.into_iter().skip(self.keep) {
+            fs::remove_file(self.path(&version.id))?;
+        }
+        Ok(())
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.conf", id))
+    }
+}
+
+#[cfg(test)]
+fn temp_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("wg-history-{}", rand::random::<u64>()))
+}
+
+#[test]
+fn test_write_atomic() {
+    let dir = temp_dir();
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("wg0.conf");
+
+    write_atomic(&path, "first").unwrap();
+    write_atomic(&path, "second").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_config_history() {
+    let dir = temp_dir();
+    let history = ConfigHistory::new(&dir, 3);
+    assert!(history.list().unwrap().is_empty());
+
+    let saved: Vec<ConfigVersion> = (0..5)
+        .map(|i| history.save(&format!("config {}", i)).unwrap())
+        .collect();
+
+    let versions = history.list().unwrap();
+    assert_eq!(versions.len(), 3);
+    assert_eq!(versions[0], saved[4]);
+    assert_eq!(versions[2], saved[2]);
+    assert_eq!(history.read(&saved[3].id).unwrap(), "config 3");
+    assert!(history.read(&saved[0].id).is_err());
+    assert!(history.read("../wg0").is_err());
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -1,2 +1,6 @@
+pub mod backend;
 pub mod config;
+pub mod diff;
+pub mod dump;
+pub mod history;
 pub mod keys;
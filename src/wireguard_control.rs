@@ -1,81 +1,470 @@
 mod cfg;
+mod crypto;
+mod metrics;
 mod rpc;
 mod statistics;
 mod storage;
+mod telemetry;
 mod wireguard;
 
-use execute::Execute;
 use rpc::wireguard::{
-    wireguard_control_server, Client, GetStatisticsRequest, GetStatisticsResponse, Server,
-    StartWireguardRequest, StartWireguardResponse, StatisticsEntry, SyncConfigRequest,
-    SyncConfigResponse,
+    wireguard_control_server, Client, GetInterfaceStatusRequest, GetInterfaceStatusResponse,
+    GetStatisticsRequest, GetStatisticsResponse, RestartWireguardRequest, RestartWireguardResponse,
+    Server, StartWireguardRequest, StartWireguardResponse, StatisticsEntry, StopWireguardRequest,
+    StopWireguardResponse, SyncConfigRequest, SyncConfigResponse, UpdatePeersRequest,
+    UpdatePeersResponse, PreviewSyncResponse, PeerChange, FieldChange, ConfigVersion,
+    ListConfigVersionsRequest, ListConfigVersionsResponse, RollbackConfigRequest,
+    RollbackConfigResponse,
+};
+use wireguard::{
+    backend::{Backend, MockBackend, ShellBackend},
+    history::{write_atomic, ConfigHistory},
 };
 use statistics::ClientEntry;
-use tonic::{async_trait, Request, Response, Status};
-use std::{path::PathBuf, str::FromStr};
+use tonic_health::{server::HealthReporter, ServingStatus};
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts};
+use opentelemetry::{global, propagation::Extractor};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tonic::{
+    async_trait,
+    codegen::{http, BoxFuture, Context, Poll, Service},
+    service::Interceptor,
+    transport::{Certificate, Identity, ServerTlsConfig},
+    Request, Response, Status,
+};
+use std::{path::PathBuf, sync::Arc, time::Instant};
+
+const SERVICE_NAME: &str = "wireguard_control.WireguardControl";
+/// Used by requests which do not name an interface, e.g. from older bots
+const DEFAULT_INTERFACE: &str = "wg0";
+
+/// Metrics of the control service, served by `metrics::serve`.
+struct ControlMetrics {
+    registry: prometheus::Registry,
+    /// Labeled by method and gRPC status code
+    grpc_duration: HistogramVec,
+    grpc_errors: IntCounterVec,
+    /// Labeled by interface
+    sync_duration: HistogramVec,
+}
+
+impl ControlMetrics {
+    fn new() -> anyhow::Result<Self> {
+        let registry = prometheus::Registry::new_custom(Some("wireguard_control".into()), None)?;
+        let metrics = Self {
+            grpc_duration: HistogramVec::new(
+                HistogramOpts::new("grpc_request_duration_seconds", "Duration of handled gRPC calls"),
+                &["method", "code"],
+            )?,
+            grpc_errors: IntCounterVec::new(
+                Opts::new("grpc_errors_total", "gRPC calls which returned an error"),
+                &["method", "code"],
+            )?,
+            sync_duration: HistogramVec::new(
+                HistogramOpts::new("sync_duration_seconds", "Duration of applying a full config"),
+                &["interface"],
+            )?,
+            registry,
+        };
+        metrics.registry.register(Box::new(metrics.grpc_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.grpc_errors.clone()))?;
+        metrics.registry.register(Box::new(metrics.sync_duration.clone()))?;
+        Ok(metrics)
+    }
+}
 
-const CONFIG_PATH: &'static str = "/etc/wireguard/wg0.conf";
+/// Records duration and status of every gRPC call, rejected ones included.
+#[derive(Clone)]
+struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<ControlMetrics>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for MetricsService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
 
-pub struct WireguardControlServer {}
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        // Paths look like `/wireguard_control.WireguardControl/SyncConfig`
+        let method = request.uri().path().rsplit('/').next().unwrap_or_default().to_owned();
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response = future.await;
+            // Unary calls carry errors in headers, successful ones only in trailers
+            let code = match &response {
+                Ok(response) => response
+                    .headers()
+                    .get("grpc-status")
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or("0")
+                    .to_owned(),
+                Err(_) => "transport".to_owned(),
+            };
+            metrics
+                .grpc_duration
+                .with_label_values(&[&method, &code])
+                .observe(started.elapsed().as_secs_f64());
+            if code != "0" {
+                metrics.grpc_errors.with_label_values(&[&method, &code]).inc();
+            }
+            response
+        })
+    }
+}
+
+/// Names end up in shell commands and file paths, so only what Linux allows
+/// for interface names is accepted.
+fn check_interface_name(name: &str) -> Result<(), Status> {
+    let valid = !name.is_empty()
+        && name.len() <= 15
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '=' | '+' | '.' | '-'));
+    if !valid {
+        return Err(Status::invalid_argument(format!("Invalid interface name: {}", name)));
+    }
+    Ok(())
+}
+
+/// Health service name of a single interface.
+fn interface_service_name(interface: &str) -> String {
+    format!("{}/{}", SERVICE_NAME, interface)
+}
+
+/// Each interface is reported separately. The service as a whole is healthy
+/// only while all managed interfaces are up, not just while listening.
+async fn report_health(health_reporter: &HealthReporter, backend: &dyn Backend, interfaces: &[String]) {
+    let status = |up: bool| if up { ServingStatus::Serving } else { ServingStatus::NotServing };
+    let mut health_reporter = health_reporter.clone();
+    let mut all_up = true;
+    for name in interfaces {
+        let up = backend.exists(name);
+        all_up &= up;
+        health_reporter
+            .set_service_status(interface_service_name(name), status(up))
+            .await;
+    }
+    health_reporter.set_service_status("", status(all_up)).await;
+    health_reporter.set_service_status(SERVICE_NAME, status(all_up)).await;
+}
+
+pub struct WireguardControlServer {
+    health_reporter: HealthReporter,
+    backend: Arc<dyn Backend>,
+    interfaces: Vec<String>,
+    config_dir: PathBuf,
+    history_dir: PathBuf,
+    history_size: usize,
+    metrics: Arc<ControlMetrics>,
+}
+
+/// Keys end up in shell commands, so anything but base64 is rejected.
+fn check_key(key: &str) -> Result<(), Status> {
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/' || c == '=');
+    if !valid {
+        return Err(Status::invalid_argument(format!("Invalid key: {}", key)));
+    }
+    Ok(())
+}
 
 impl WireguardControlServer {
+    /// Only interfaces listed in the config can be managed, so a client can
+    /// not create arbitrary ones.
+    fn interface(&self, name: &str) -> Result<Interface, Status> {
+        let name = if name.is_empty() { DEFAULT_INTERFACE } else { name };
+        if !self.interfaces.iter().any(|i| i == name) {
+            return Err(Status::not_found(format!("Interface {} is not managed", name)));
+        }
+        Ok(Interface {
+            name: name.to_owned(),
+            config_path: self.config_dir.join(format!("{}.conf", name)),
+            history: ConfigHistory::new(self.history_dir.join(name), self.history_size),
+            backend: self.backend.clone(),
+        })
+    }
+
+    async fn report_health(&self) {
+        report_health(&self.health_reporter, self.backend.as_ref(), &self.interfaces).await;
+    }
+}
+
+/// One WireGuard interface with its config file and config history.
+struct Interface {
+    name: String,
+    config_path: PathBuf,
+    history: ConfigHistory,
+    backend: Arc<dyn Backend>,
+}
+
+impl Interface {
+    fn exists(&self) -> bool {
+        self.backend.exists(&self.name)
+    }
+
+    fn write_config(&self, content: &str) -> Result<(), Status> {
+        write_atomic(&self.config_path, content)
+            .map_err(|e| Status::internal(format!("Failed to write config: {}", e)))
+    }
+
     fn apply_config(&self, server: &Server, clients: &Vec<Client>) -> Result<(), Status> {
-        let cfg = wireguard::config::build_server_config(&server, &clients)?;
-        let path = PathBuf::from(CONFIG_PATH);
-        std::fs::write(path, cfg)
-            .map_err(|e| Status::internal(format!("Failed to write config: {}", e)))?;
-
-        let mut cmd = execute::shell("wg syncconf wg0 <(wg-quick strip wg0)");
-        let result = cmd
-            .execute()
-            .map_err(|e| Status::internal(format!("Failed to sync config: {}", e)))?;
-
-        let exit_code = result.unwrap_or(0);
-        if exit_code != 0 {
+        let cfg = wireguard::config::build_server_config(server, clients)?;
+        self.apply_raw_config(&cfg)
+    }
+
+    /// Replaces the config and syncs the interface with it. If syncing fails,
+    /// the previous config is restored, so the next `wg-quick up` still works.
+    fn apply_raw_config(&self, cfg: &str) -> Result<(), Status> {
+        let previous = std::fs::read_to_string(&self.config_path).ok();
+        self.write_config(cfg)?;
+
+        if let Err(status) = self.backend.sync(&self.name, &self.config_path) {
+            let previous = match previous {
+                Some(previous) => previous,
+                None => return Err(status),
+            };
+            tracing::error!("{}, restoring previous config of {}", status.message(), self.name);
+            self.write_config(&previous)?;
+            self.backend.sync(&self.name, &self.config_path)?;
             return Err(Status::internal(format!(
-                "Config sync finished with non-successed exit status: {}",
-                exit_code
+                "{}. Previous config was restored",
+                status.message()
             )));
         }
+
+        self.save_version(cfg);
         Ok(())
     }
 
-    fn start_wireguard(&self, server: &Server) -> Result<(), Status> {
-        let cfg = wireguard::config::build_server_config(&server, &vec![])?;
-        let path = PathBuf::from(CONFIG_PATH);
-        std::fs::write(path, cfg)
-            .map_err(|e| Status::internal(format!("Failed to write config: {}", e)))?;
+    /// History is best effort, a failure to record it never fails the sync.
+    fn save_version(&self, cfg: &str) {
+        match self.history.save(cfg) {
+            Ok(version) => tracing::debug!("Saved config version {} of {}", version.id, self.name),
+            Err(e) => tracing::error!("Failed to save config version of {}: {}", self.name, e),
+        }
+    }
 
-        let mut cmd = execute::shell("wg-quick up wg0");
-        let result = cmd
-            .execute()
-            .map_err(|e| Status::internal(format!("Failed to up wg0 interface: {}", e)))?;
-        let exit_code = result.unwrap_or(0);
-        if exit_code != 0 {
-            return Err(Status::internal(format!(
-                "Wireguard starting finished with non-successed exit status: {}",
-                exit_code
-            )));
+    fn list_config_versions(&self) -> Result<Vec<ConfigVersion>, Status> {
+        Ok(self
+            .history
+            .list()
+            .map_err(|e| Status::internal(format!("Failed to list config versions: {}", e)))?
+            .into_iter()
+            .map(|v| ConfigVersion { id: v.id, timestamp: v.timestamp, size: v.size })
+            .collect())
+    }
+
+    fn rollback_config(&self, id: &str) -> Result<(), Status> {
+        let cfg = self.history.read(id).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound | std::io::ErrorKind::InvalidInput => {
+                Status::not_found(format!("Config version {} not found", id))
+            }
+            _ => Status::internal(format!("Failed to read config version {}: {}", id, e)),
+        })?;
+        tracing::info!("Rolling back {} to config version {}", self.name, id);
+        self.apply_raw_config(&cfg)
+    }
+
+    /// Brings the interface up, or reconciles it with `server` if it is
+    /// already up. Live peers are kept, the bot replaces them with
+    /// `SyncConfig` anyway.
+    fn start(&self, server: &Server) -> Result<(), Status> {
+        if self.exists() {
+            tracing::info!("{} is already up, reconciling its config", self.name);
+            let (entries, warnings) = self.get_statistics()?;
+            // Skipped peers would be removed by the new config
+            if !warnings.is_empty() {
+                return Err(Status::internal(format!(
+                    "Could not parse the peers of {}: {}",
+                    self.name,
+                    warnings.join("; ")
+                )));
+            }
+            let clients = entries
+                .into_iter()
+                .map(|e| Client { key: e.public_key, ip: e.ip })
+                .collect();
+            return self.apply_config(server, &clients);
         }
+
+        let cfg = wireguard::config::build_server_config(server, &vec![])?;
+        self.write_config(&cfg)?;
+        self.backend.up(&self.name, &self.config_path)?;
+        self.save_version(&cfg);
         Ok(())
     }
 
-    fn get_statistics(&self) -> Result<Vec<StatisticsEntry>, Status> {
-        let mut cmd = execute::shell("wg show wg0 dump");
-        let output = cmd
-            .output()
-            .map_err(|e| Status::internal(format!("Could not get statistics info: {}", e)))?;
-        let data = String::from_utf8(output.stdout)
-            .map_err(|e| Status::internal(format!("Could not get string from output: {}", e)))?;
-        let mut entries: Vec<StatisticsEntry> = vec![];
-        for line in data.lines().skip(1) {
-            let entry = ClientEntry::from_str(&line).map_err(|e| {
-                Status::internal(format!("Count not convert statistics entry: {}", e))
-            })?;
-            entries.push(entry.into());
+    /// Changes live peers one by one without touching the rest, then saves
+    /// the result to the config file.
+    fn update_peers(&self, upsert: &Vec<Client>, remove: &Vec<String>) -> Result<(), Status> {
+        for client in upsert {
+            check_key(&client.key)?;
+            self.backend.set_peer(&self.name, client)?;
+        }
+        for key in remove {
+            check_key(key)?;
+            self.backend.remove_peer(&self.name, key)?;
         }
-        Ok(entries)
+
+        // The running interface already has the peers, the config keeps them
+        // across restarts
+        let text = std::fs::read_to_string(&self.config_path)
+            .map_err(|e| Status::internal(format!("Failed to read config: {}", e)))?;
+        let mut config = wireguard::config::parse_server_config(&text).map_err(Status::internal)?;
+        for client in upsert {
+            match config.peers.iter_mut().find(|peer| peer.key == client.key) {
+                Some(peer) => peer.ip = client.ip,
+                None => config.peers.push(client.clone()),
+            }
+        }
+        config.peers.retain(|peer| !remove.contains(&peer.key));
+        let cfg = config.render();
+        self.write_config(&cfg)?;
+        self.save_version(&cfg);
+        Ok(())
     }
+
+    /// Compares the config `apply_config` would write with the one on disk.
+    /// Nothing on the system is changed.
+    fn preview_sync(&self, server: &Server, clients: &Vec<Client>) -> Result<PreviewSyncResponse, Status> {
+        let desired = wireguard::config::build_server_config(server, clients)?;
+        let desired = wireguard::config::parse_server_config(&desired).map_err(Status::internal)?;
+        let current = match std::fs::read_to_string(&self.config_path) {
+            Ok(text) => wireguard::config::parse_server_config(&text)
+                .map_err(|e| Status::internal(format!("Could not parse current config: {}", e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(Status::internal(format!("Failed to read config: {}", e))),
+        };
+
+        let peers = wireguard::diff::diff_peers(&desired.peers, &current.peers);
+        let interface_changes = wireguard::diff::diff_interface(&current.interface, &desired.interface);
+        Ok(PreviewSyncResponse {
+            peers_to_add: peers.add,
+            peers_to_remove: peers.remove,
+            peers_to_modify: peers
+                .modify
+                .into_iter()
+                .map(|c| PeerChange {
+                    current_ip: current.peers.iter().find(|p| p.key == c.key).map_or(0, |p| p.ip),
+                    desired_ip: c.ip,
+                    key: c.key,
+                })
+                .collect(),
+            interface_changes: interface_changes
+                .into_iter()
+                .map(|c| FieldChange { field: c.field, current: c.current, desired: c.desired })
+                .collect(),
+        })
+    }
+
+    fn stop(&self) -> Result<(), Status> {
+        if !self.exists() {
+            return Ok(());
+        }
+        self.backend.down(&self.name, &self.config_path)
+    }
+
+    fn get_status(&self) -> Result<GetInterfaceStatusResponse, Status> {
+        if !self.exists() {
+            return Ok(GetInterfaceStatusResponse::default());
+        }
+
+        let dump = wireguard::dump::parse_dump(&self.dump()?);
+        let interface = dump.interface.ok_or_else(|| {
+            Status::internal(format!("No interface line in `wg show {} dump`", self.name))
+        })?;
+        Ok(GetInterfaceStatusResponse {
+            exists: true,
+            listen_port: interface.listen_port.into(),
+            public_key: interface.public_key,
+            // Malformed peers are counted as well, they are still configured
+            peer_count: (dump.peers.len() + dump.errors.len()) as u32,
+        })
+    }
+
+    fn dump(&self) -> Result<String, Status> {
+        self.backend.dump(&self.name)
+    }
+
+    /// Peers which could be parsed, with a warning for every malformed line
+    /// of the dump.
+    fn get_statistics(&self) -> Result<(Vec<StatisticsEntry>, Vec<String>), Status> {
+        let dump = wireguard::dump::parse_dump(&self.dump()?);
+        let warnings: Vec<String> = dump.errors.iter().map(|e| e.to_string()).collect();
+        for warning in &warnings {
+            tracing::warn!("Skipped a line of `wg show {} dump`: {}", self.name, warning);
+        }
+        let entries = dump
+            .peers
+            .into_iter()
+            .map(|peer| ClientEntry::from(peer).into())
+            .collect();
+        Ok((entries, warnings))
+    }
+}
+
+/// Rejects requests without the shared bearer token, if one is configured.
+#[derive(Clone)]
+struct TokenInterceptor {
+    expected: Option<String>,
+}
+
+impl Interceptor for TokenInterceptor {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let expected = match &self.expected {
+            Some(expected) => expected,
+            None => return Ok(request),
+        };
+        let provided = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+            Ok(request)
+        } else {
+            Err(Status::unauthenticated("Invalid or missing token"))
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+struct HeaderExtractor<'a>(&'a http::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Span of an incoming gRPC request, continuing the trace of the caller.
+fn grpc_span(request: &http::Request<()>) -> tracing::Span {
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    let span = tracing::info_span!("grpc", method = %request.uri().path());
+    span.set_parent(parent);
+    span
 }
 
 #[async_trait]
@@ -84,9 +473,15 @@ impl rpc::wireguard::wireguard_control_server::WireguardControl for WireguardCon
         &self,
         request: Request<SyncConfigRequest>,
     ) -> Result<Response<SyncConfigResponse>, Status> {
-        let SyncConfigRequest { server, clients } = request.into_inner();
+        let SyncConfigRequest { server, clients, interface } = request.into_inner();
         let server = server.ok_or(Status::invalid_argument("Field `server` is empty"))?;
-        let _ = self.apply_config(&server, &clients)?;
+        let interface = self.interface(&interface)?;
+        let started = Instant::now();
+        let _ = interface.apply_config(&server, &clients)?;
+        self.metrics
+            .sync_duration
+            .with_label_values(&[&interface.name])
+            .observe(started.elapsed().as_secs_f64());
         Ok(Response::new(SyncConfigResponse {}))
     }
 
@@ -94,40 +489,173 @@ impl rpc::wireguard::wireguard_control_server::WireguardControl for WireguardCon
         &self,
         request: Request<StartWireguardRequest>,
     ) -> Result<Response<StartWireguardResponse>, Status> {
-        let StartWireguardRequest { server } = request.into_inner();
+        let StartWireguardRequest { server, interface } = request.into_inner();
         let server = server.ok_or(Status::invalid_argument("Field `server` is empty"))?;
-        let _ = self.start_wireguard(&server)?;
+        let _ = self.interface(&interface)?.start(&server)?;
+        self.report_health().await;
         Ok(Response::new(StartWireguardResponse {}))
     }
 
     async fn get_statistics(
         &self,
-        _request: Request<GetStatisticsRequest>,
+        request: Request<GetStatisticsRequest>,
     ) -> Result<Response<GetStatisticsResponse>, Status> {
-        let entries = self.get_statistics()?;
-        Ok(Response::new(GetStatisticsResponse { entries }))
+        let GetStatisticsRequest { interface } = request.into_inner();
+        let (entries, warnings) = self.interface(&interface)?.get_statistics()?;
+        Ok(Response::new(GetStatisticsResponse { entries, warnings }))
     }
-}
 
-use tracing::level_filters::LevelFilter;
-use tracing_subscriber::{prelude::*, registry::Registry};
+    async fn stop_wireguard(
+        &self,
+        request: Request<StopWireguardRequest>,
+    ) -> Result<Response<StopWireguardResponse>, Status> {
+        let StopWireguardRequest { interface } = request.into_inner();
+        let result = self.interface(&interface)?.stop();
+        self.report_health().await;
+        result?;
+        Ok(Response::new(StopWireguardResponse {}))
+    }
+
+    async fn restart_wireguard(
+        &self,
+        request: Request<RestartWireguardRequest>,
+    ) -> Result<Response<RestartWireguardResponse>, Status> {
+        let RestartWireguardRequest { server, interface } = request.into_inner();
+        let server = server.ok_or(Status::invalid_argument("Field `server` is empty"))?;
+        let interface = self.interface(&interface)?;
+        let result = interface.stop().and_then(|_| interface.start(&server));
+        self.report_health().await;
+        result?;
+        Ok(Response::new(RestartWireguardResponse {}))
+    }
+
+    async fn get_interface_status(
+        &self,
+        request: Request<GetInterfaceStatusRequest>,
+    ) -> Result<Response<GetInterfaceStatusResponse>, Status> {
+        let GetInterfaceStatusRequest { interface } = request.into_inner();
+        Ok(Response::new(self.interface(&interface)?.get_status()?))
+    }
+
+    async fn preview_sync(
+        &self,
+        request: Request<SyncConfigRequest>,
+    ) -> Result<Response<PreviewSyncResponse>, Status> {
+        let SyncConfigRequest { server, clients, interface } = request.into_inner();
+        let server = server.ok_or(Status::invalid_argument("Field `server` is empty"))?;
+        Ok(Response::new(self.interface(&interface)?.preview_sync(&server, &clients)?))
+    }
+
+    async fn list_config_versions(
+        &self,
+        request: Request<ListConfigVersionsRequest>,
+    ) -> Result<Response<ListConfigVersionsResponse>, Status> {
+        let ListConfigVersionsRequest { interface } = request.into_inner();
+        let versions = self.interface(&interface)?.list_config_versions()?;
+        Ok(Response::new(ListConfigVersionsResponse { versions }))
+    }
+
+    async fn rollback_config(
+        &self,
+        request: Request<RollbackConfigRequest>,
+    ) -> Result<Response<RollbackConfigResponse>, Status> {
+        let RollbackConfigRequest { id, interface } = request.into_inner();
+        self.interface(&interface)?.rollback_config(&id)?;
+        Ok(Response::new(RollbackConfigResponse {}))
+    }
+
+    async fn update_peers(
+        &self,
+        request: Request<UpdatePeersRequest>,
+    ) -> Result<Response<UpdatePeersResponse>, Status> {
+        let UpdatePeersRequest { upsert, remove, interface } = request.into_inner();
+        self.interface(&interface)?.update_peers(&upsert, &remove)?;
+        Ok(Response::new(UpdatePeersResponse {}))
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    let fmt_layer = tracing_subscriber::fmt::layer()
-        .with_target(false)
-        .with_filter(LevelFilter::DEBUG);
+    telemetry::init(&cfg::get_log_config()?, "wireguard_control")?;
+    let control_cfg = cfg::get_control_server_config()?;
+    for name in &control_cfg.interfaces {
+        check_interface_name(name)?;
+    }
+    let address = control_cfg.listen_address.parse()?;
+    let interceptor = TokenInterceptor {
+        expected: cfg::read_auth_token(&control_cfg.auth_token, &control_cfg.auth_token_file)?
+            .map(|token| format!("Bearer {}", token)),
+    };
+    let backend: Arc<dyn Backend> = match control_cfg.backend {
+        cfg::ControlBackend::Shell => Arc::new(ShellBackend),
+        cfg::ControlBackend::Mock => {
+            tracing::warn!("Using mock backend, no WireGuard interface is touched");
+            Arc::new(MockBackend::default())
+        }
+    };
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    report_health(&health_reporter, backend.as_ref(), &control_cfg.interfaces).await;
+    {
+        let health_reporter = health_reporter.clone();
+        let backend = backend.clone();
+        let interfaces = control_cfg.interfaces.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                report_health(&health_reporter, backend.as_ref(), &interfaces).await;
+            }
+        });
+    }
+
+    let control_metrics = Arc::new(ControlMetrics::new()?);
+    if let Some(address) = &control_cfg.metrics_address {
+        metrics::serve(address, control_metrics.registry.clone())?;
+    }
+
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(rpc::wireguard::FILE_DESCRIPTOR_SET)
+        .build()?;
 
-    Registry::default().with(fmt_layer).try_init().unwrap();
+    let wg_control_server = WireguardControlServer {
+        health_reporter,
+        backend,
+        interfaces: control_cfg.interfaces.clone(),
+        config_dir: PathBuf::from(&control_cfg.config_dir),
+        history_dir: PathBuf::from(&control_cfg.config_history_dir),
+        history_size: control_cfg.config_history_size,
+        metrics: control_metrics.clone(),
+    };
 
-    let address = "0.0.0.0:8080".parse().unwrap();
-    let wg_control_server = WireguardControlServer {};
+    let mut builder = tonic::transport::Server::builder();
+    if let Some(tls) = &control_cfg.tls {
+        let mut tls_config = ServerTlsConfig::new().identity(Identity::from_pem(
+            std::fs::read(&tls.cert_file)?,
+            std::fs::read(&tls.key_file)?,
+        ));
+        if let Some(client_ca_file) = &tls.client_ca_file {
+            tls_config = tls_config.client_ca_root(Certificate::from_pem(std::fs::read(client_ca_file)?));
+        }
+        builder = builder.tls_config(tls_config)?;
+    }
+    if interceptor.expected.is_none() && control_cfg.tls.is_none() {
+        tracing::warn!("Control API is served without TLS and authentication");
+    }
 
-    tonic::transport::Server::builder()
-        .add_service(wireguard_control_server::WireguardControlServer::new(
+    // Health and reflection stay unauthenticated, so that generic tooling can probe them
+    builder
+        .trace_fn(grpc_span)
+        .layer(tower::layer::layer_fn(move |inner| MetricsService {
+            inner,
+            metrics: control_metrics.clone(),
+        }))
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(wireguard_control_server::WireguardControlServer::with_interceptor(
             wg_control_server,
+            interceptor,
         ))
         .serve(address)
         .await?;
+    tokio::task::spawn_blocking(telemetry::shutdown).await?;
     Ok(())
 }